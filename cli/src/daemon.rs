@@ -0,0 +1,221 @@
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use shared::keymap::PROFILE_COUNT;
+use shared::message::Message;
+use crate::focus::{Focus, FocusSource};
+use crate::session::{Session, SessionEvent};
+
+/// Maps focused applications to profiles, the first matching rule wins.
+///
+/// ```toml
+/// default = 0
+///
+/// [[rule]]
+/// process = "osu!"
+/// profile = 1
+///
+/// [[rule]]
+/// class = "steam_app_730"
+/// profile = 1
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct Rules {
+    #[serde(default)]
+    pub default: u8,
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Rule {
+    pub process: Option<String>,
+    pub class: Option<String>,
+    pub profile: u8,
+}
+
+impl Rule {
+    fn matches(&self, focus: &Focus) -> bool {
+        if self.process.is_none() && self.class.is_none() {
+            return false;
+        }
+        let process = self.process.is_none() || self.process == focus.process;
+        let class = self.class.is_none() || self.class == focus.class;
+        process && class
+    }
+}
+
+impl Rules {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("Reading {}: {e}", path.display()))?;
+        let rules: Self = toml::from_str(&content).map_err(|e| format!("Parsing {}: {e}", path.display()))?;
+        rules.check().map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(rules)
+    }
+
+    /// The pad keeps its profile on unknown ones, so they are refused up front.
+    fn check(&self) -> Result<(), String> {
+        let mut profiles = std::iter::once(self.default).chain(self.rules.iter().map(|rule| rule.profile));
+        match profiles.find(|profile| *profile as usize >= PROFILE_COUNT) {
+            Some(profile) => Err(format!("profile {profile} does not exist, the pad has {PROFILE_COUNT}")),
+            None => Ok(()),
+        }
+    }
+
+    pub fn profile_for(&self, focus: Option<&Focus>) -> u8 {
+        focus
+            .and_then(|focus| self.rules.iter().find(|rule| rule.matches(focus)))
+            .map(|rule| rule.profile)
+            .unwrap_or(self.default)
+    }
+}
+
+/// Decides when the pad has to be told about a new profile.
+pub struct Switcher {
+    rules: Rules,
+    sent: Option<u8>,
+}
+
+impl Switcher {
+    pub fn new(rules: Rules) -> Self {
+        Self {
+            rules,
+            sent: None,
+        }
+    }
+
+    /// Returns the profile to switch to, if it differs from the last one sent.
+    pub fn update(&mut self, focus: Option<&Focus>) -> Option<u8> {
+        let profile = self.rules.profile_for(focus);
+        (self.sent != Some(profile)).then_some(profile)
+    }
+
+    pub fn sent(&mut self, profile: u8) {
+        self.sent = Some(profile);
+    }
+
+    /// Forget what the pad was told, e.g. because it was replugged and lost its state.
+    pub fn reset(&mut self) {
+        self.sent = None;
+    }
+}
+
+pub fn run(mut session: Session, mut source: impl FocusSource, rules: Rules) {
+    let mut switcher = Switcher::new(rules);
+
+    loop {
+        // Also paces the loop, polling waits a little when nothing happens
+        while let Some(event) = session.poll_event() {
+            match event {
+                SessionEvent::Connected { bus, address } => {
+                    println!("Connected: Bus {bus:03} Device {address:03}");
+                    switcher.reset();
+                }
+                SessionEvent::Disconnected => {
+                    println!("Disconnected");
+                    switcher.reset();
+                }
+                SessionEvent::Message(Message::ActiveProfile(profile)) => {
+                    println!("Active profile: {profile}");
+                }
+                SessionEvent::Message(Message::Error(code)) => {
+                    eprintln!("Pad refused the request: {code:?}");
+                }
+                SessionEvent::Message(_) => {}
+            }
+        }
+
+        let focus = source.focused();
+        if let Some(profile) = switcher.update(focus.as_ref()) {
+            if session.send(&Message::SetProfile(profile)) {
+                switcher.sent(profile);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use shared::keymap::PROFILE_COUNT;
+    use crate::daemon::{Rules, Switcher};
+    use crate::focus::{Focus, FocusSource};
+
+    /// Replays a fixed sequence of focus changes.
+    struct FakeFocusSource(std::vec::IntoIter<Option<Focus>>);
+
+    impl FocusSource for FakeFocusSource {
+        fn focused(&mut self) -> Option<Focus> {
+            self.0.next().flatten()
+        }
+    }
+
+    fn focus(process: &str, class: &str) -> Option<Focus> {
+        Some(Focus {
+            process: Some(process.to_owned()),
+            class: Some(class.to_owned()),
+        })
+    }
+
+    fn rules() -> Rules {
+        toml::from_str(r#"
+            default = 0
+
+            [[rule]]
+            process = "osu!"
+            profile = 1
+
+            [[rule]]
+            class = "steam_app_730"
+            profile = 2
+        "#).unwrap()
+    }
+
+    #[test]
+    fn test_switches_on_focus_change() {
+        let mut source = FakeFocusSource(vec![
+            focus("bash", "kitty"),
+            focus("osu!", "osu!.exe"),
+            focus("osu!", "osu!.exe"),
+            focus("csgo_linux64", "steam_app_730"),
+            None,
+        ].into_iter());
+        let mut switcher = Switcher::new(rules());
+
+        let mut switches = Vec::new();
+        for _ in 0..5 {
+            if let Some(profile) = switcher.update(source.focused().as_ref()) {
+                switcher.sent(profile);
+                switches.push(profile);
+            }
+        }
+        assert_eq!(switches, [0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn test_resends_after_reset() {
+        let mut source = FakeFocusSource(vec![focus("osu!", "osu!.exe"); 2].into_iter());
+        let mut switcher = Switcher::new(rules());
+
+        let profile = switcher.update(source.focused().as_ref()).unwrap();
+        switcher.sent(profile);
+        switcher.reset();
+        assert_eq!(switcher.update(source.focused().as_ref()), Some(1));
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        assert_eq!(rules().check(), Ok(()));
+
+        let rules: Rules = toml::from_str(&format!("[[rule]]\nprocess = \"osu!\"\nprofile = {PROFILE_COUNT}")).unwrap();
+        assert!(rules.check().is_err());
+        let rules: Rules = toml::from_str(&format!("default = {PROFILE_COUNT}")).unwrap();
+        assert!(rules.check().is_err());
+    }
+
+    #[test]
+    fn test_unsent_profile_is_retried() {
+        let mut switcher = Switcher::new(rules());
+        assert_eq!(switcher.update(focus("osu!", "").as_ref()), Some(1));
+        assert_eq!(switcher.update(focus("osu!", "").as_ref()), Some(1));
+    }
+}
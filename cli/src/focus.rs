@@ -0,0 +1,50 @@
+use std::fs;
+use std::process::Command;
+
+/// The application currently receiving keyboard input.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Focus {
+    pub process: Option<String>,
+    pub class: Option<String>,
+}
+
+pub trait FocusSource {
+    /// `None` if nothing is focused or the focus could not be determined.
+    fn focused(&mut self) -> Option<Focus>;
+}
+
+/// Asks the X server through `xprop`, also covers XWayland windows.
+pub struct X11FocusSource;
+
+impl FocusSource for X11FocusSource {
+    fn focused(&mut self) -> Option<Focus> {
+        let root = xprop(&["-root", "_NET_ACTIVE_WINDOW"])?;
+        // _NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007
+        let window = root.split_whitespace().last()?.to_owned();
+        if window == "0x0" {
+            return None;
+        }
+
+        let props = xprop(&["-id", &window, "WM_CLASS", "_NET_WM_PID"])?;
+        let mut focus = Focus::default();
+        for line in props.lines() {
+            if let Some(class) = line.strip_prefix("WM_CLASS(STRING) = ") {
+                // WM_CLASS(STRING) = "navigator", "firefox"
+                focus.class = class.rsplit(", ").next().map(|c| c.trim_matches('"').to_owned());
+            } else if let Some(pid) = line.strip_prefix("_NET_WM_PID(CARDINAL) = ") {
+                focus.process = fs::read_to_string(format!("/proc/{}/comm", pid.trim()))
+                    .ok()
+                    .map(|comm| comm.trim_end().to_owned());
+            }
+        }
+        Some(focus)
+    }
+}
+
+fn xprop(args: &[&str]) -> Option<String> {
+    let output = Command::new("xprop").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
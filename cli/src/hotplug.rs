@@ -0,0 +1,73 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use rusb::{Context, Device, Hotplug, HotplugBuilder, UsbContext};
+use shared::{PRODUCT_ID, VENDOR_ID};
+use crate::kb_handle::find_keyboard;
+
+/// How often the device list is rescanned when libusb has no hotplug support.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub enum HotplugEvent {
+    Arrived(Device<Context>),
+    Left,
+}
+
+struct Forwarder(Sender<HotplugEvent>);
+
+impl Hotplug<Context> for Forwarder {
+    fn device_arrived(&mut self, device: Device<Context>) {
+        let _ = self.0.send(HotplugEvent::Arrived(device));
+    }
+
+    fn device_left(&mut self, _device: Device<Context>) {
+        let _ = self.0.send(HotplugEvent::Left);
+    }
+}
+
+/// Reports the pad arriving and leaving, including a pad that is already plugged in.
+///
+/// Uses libusb hotplug callbacks where available and falls back to polling the device list.
+pub fn watch(context: Context) -> Receiver<HotplugEvent> {
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        if rusb::has_hotplug() {
+            // Only forward from the callback, libusb forbids doing IO in there
+            let _registration = HotplugBuilder::new()
+                .vendor_id(VENDOR_ID)
+                .product_id(PRODUCT_ID)
+                .enumerate(true)
+                .register(&context, Box::new(Forwarder(sender)))
+                .unwrap();
+
+            loop {
+                context.handle_events(None).unwrap();
+            }
+        } else {
+            poll(context, sender);
+        }
+    });
+
+    receiver
+}
+
+fn poll(context: Context, sender: Sender<HotplugEvent>) {
+    let mut present: Option<Device<Context>> = None;
+    loop {
+        let found = find_keyboard(&context);
+        let event = match (&present, &found) {
+            (None, Some(device)) => Some(HotplugEvent::Arrived(device.clone())),
+            (Some(_), None) => Some(HotplugEvent::Left),
+            _ => None,
+        };
+        present = found;
+
+        if let Some(event) = event {
+            if sender.send(event).is_err() {
+                return;
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
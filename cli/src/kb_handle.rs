@@ -1,5 +1,149 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::time::Duration;
+use hidapi::{HidApi, HidResult};
+use rusb::{Context, Device, DeviceHandle, Direction, TransferType, UsbContext};
+use shared::host::{self, find_message_interface, AnalogReader};
+use shared::message::{Message, MESSAGE_BUF_SIZE};
+use shared::report::KEYBOARD_REPORT_ID;
+use shared::{PRODUCT_ID, VENDOR_ID};
 
+const HID_CLASS: u8 = 0x03;
+/// Long enough for requests that erase flash sectors, like saving or a factory reset.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct KeyboardHandle {
+    handle: DeviceHandle<Context>,
+    interface: u8,
+    ep_in: u8,
+    ep_out: u8,
+}
 
-}
\ No newline at end of file
+impl KeyboardHandle {
+    pub fn open(device: &Device<Context>) -> rusb::Result<Self> {
+        let (interface, ep_in, ep_out) = find_message_interface(device)?;
+
+        let handle = device.open()?;
+        handle.claim_interface(interface)?;
+
+        Ok(Self {
+            handle,
+            interface,
+            ep_in,
+            ep_out,
+        })
+    }
+
+    pub fn send(&self, msg: &Message) -> rusb::Result<()> {
+        self.handle.write_bulk(self.ep_out, msg.serialize().as_slice(), Duration::from_secs(1))?;
+        Ok(())
+    }
+
+    /// Waits for the next message from the device, a zero timeout waits forever.
+    pub fn receive(&self, timeout: Duration) -> rusb::Result<Message> {
+        let mut buf = [0; MESSAGE_BUF_SIZE];
+        let len = self.handle.read_bulk(self.ep_in, &mut buf, timeout)?;
+        Ok(Message::deserialize(&buf[..len]))
+    }
+
+    pub fn request(&self, msg: &Message) -> rusb::Result<Message> {
+        host::request(&self.handle, self.ep_in, self.ep_out, msg, REQUEST_TIMEOUT)
+    }
+
+    /// Opens the pad's analog reports through hidraw, which leaves this handle and typing alone.
+    pub fn analog_reader(&self) -> HidResult<AnalogReader> {
+        AnalogReader::open(&HidApi::new()?, None)
+    }
+}
+
+impl Drop for KeyboardHandle {
+    fn drop(&mut self) {
+        // Fails when the device is already gone, there is nothing left to release then
+        let _ = self.handle.release_interface(self.interface);
+    }
+}
+
+/// The keyboard's HID interface, taken from the kernel to see reports the moment they arrive.
+///
+/// Typing on the pad goes nowhere while this is open.
+pub struct ReportReader {
+    handle: DeviceHandle<Context>,
+    interface: u8,
+    ep_in: u8,
+}
+
+impl ReportReader {
+    pub fn open(device: &Device<Context>) -> rusb::Result<Self> {
+        let (interface, ep_in) = find_report_interface(device)?;
+
+        let handle = device.open()?;
+        // Hands the interface back to the kernel on release
+        handle.set_auto_detach_kernel_driver(true)?;
+        handle.claim_interface(interface)?;
+
+        Ok(Self {
+            handle,
+            interface,
+            ep_in,
+        })
+    }
+
+    /// Waits for the next keyboard report, a zero timeout waits forever.
+    ///
+    /// The other reports share the endpoint and are skipped, each read gets the whole timeout.
+    pub fn read(&self, timeout: Duration) -> rusb::Result<Vec<u8>> {
+        let mut buf = [0; 64];
+        loop {
+            let len = self.handle.read_interrupt(self.ep_in, &mut buf, timeout)?;
+            if buf[..len].first() == Some(&KEYBOARD_REPORT_ID) {
+                return Ok(buf[..len].to_vec());
+            }
+        }
+    }
+}
+
+impl Drop for ReportReader {
+    fn drop(&mut self) {
+        let _ = self.handle.release_interface(self.interface);
+    }
+}
+
+pub fn is_keyboard(device: &Device<Context>) -> bool {
+    device.device_descriptor()
+        .map(|d| d.vendor_id() == VENDOR_ID && d.product_id() == PRODUCT_ID)
+        .unwrap_or(false)
+}
+
+pub fn find_keyboard(context: &Context) -> Option<Device<Context>> {
+    context.devices()
+        .ok()?
+        .iter()
+        .find(is_keyboard)
+}
+
+/// Makes the device node accessible without root, escalating to sudo if needed.
+pub fn grant_permissions(device: &Device<Context>) {
+    sudo::escalate_if_needed().unwrap();
+    let f = fs::File::open(format!("/dev/bus/usb/{:03}/{:03}", device.bus_number(), device.address())).unwrap();
+    let mut perms = f.metadata().unwrap().permissions();
+    perms.set_mode(0o0666);
+    f.set_permissions(perms).unwrap();
+}
+
+fn find_report_interface(device: &Device<Context>) -> rusb::Result<(u8, u8)> {
+    let config = device.active_config_descriptor()?;
+    for interface in config.interfaces() {
+        for alt in interface.descriptors() {
+            if alt.class_code() != HID_CLASS {
+                continue;
+            }
+
+            let ep_in = alt.endpoint_descriptors()
+                .find(|e| e.transfer_type() == TransferType::Interrupt && e.direction() == Direction::In);
+            if let Some(ep_in) = ep_in {
+                return Ok((alt.interface_number(), ep_in.address()));
+            }
+        }
+    }
+    Err(rusb::Error::NotFound)
+}
@@ -0,0 +1,34 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use shared::message::Message;
+use crate::kb_handle::{KeyboardHandle, ReportReader};
+
+/// Far past the longest poll interval, a missing report means the key did not change any.
+const REPORT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Presses and releases `key` on the pad `rounds` times, returning how long each report took to arrive, sorted.
+///
+/// Includes sending the request to the pad, so this is an upper bound of what a real key press takes.
+pub fn measure(kb: &KeyboardHandle, reports: &ReportReader, key: u8, rounds: usize) -> Vec<Duration> {
+    let mut samples = Vec::with_capacity(rounds * 2);
+    for i in 0..rounds {
+        for pressed in [true, false] {
+            // Land at a different point of the scan and poll intervals every time
+            thread::sleep(Duration::from_micros(5_000 + (i as u64 * 1_237) % 5_000));
+
+            let sent = Instant::now();
+            kb.send(&Message::SimulateKey { key, pressed }).unwrap();
+            if let Err(e) = reports.read(REPORT_TIMEOUT) {
+                panic!("No report for key {key}, is it mapped to a key in the active profile? {e}");
+            }
+            samples.push(sent.elapsed());
+
+            match kb.receive(REPORT_TIMEOUT).unwrap() {
+                Message::Ack => {}
+                msg => panic!("Pad refused to simulate key {key}: {msg:?}"),
+            }
+        }
+    }
+    samples.sort();
+    samples
+}
@@ -1,34 +1,275 @@
 mod kb_handle;
+mod hotplug;
+mod session;
+mod focus;
+mod daemon;
+mod settings;
+mod latency;
 
 use std::fs;
-use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
-use rusb::{Device, GlobalContext};
-use shared::message::Message;
-use shared::VENDOR_ID;
+use clap::{Parser, Subcommand};
+use rusb::Context;
+use shared::file::{diff, Change, PadConfig};
+use shared::keymap::KEY_COUNT;
+use shared::message::{Message, Stream};
+use shared::settings::Settings;
+use crate::daemon::Rules;
+use crate::focus::X11FocusSource;
+use crate::kb_handle::{find_keyboard, grant_permissions, KeyboardHandle, ReportReader};
+use crate::session::{Session, SessionEvent};
+
+#[derive(Parser)]
+#[command(about = "Talk to a magneto_pad over its vendor interface")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check that the pad answers
+    Ping,
+    /// Print key events and follow the pad across replugs
+    Monitor,
+    /// Switch profiles depending on the focused application
+    Daemon {
+        /// TOML file mapping process names or window classes to profile ids
+        rules: PathBuf,
+    },
+    /// Make the pad match a config file and save it
+    Apply {
+        file: PathBuf,
+    },
+    /// Write the pad's config as a config file, to stdout if no file is given
+    Dump {
+        file: Option<PathBuf>,
+    },
+    /// Show how the pad differs from a config file
+    Diff {
+        file: PathBuf,
+    },
+    /// Save everything the pad stores, counters included
+    Backup {
+        file: PathBuf,
+    },
+    /// Replace everything the pad stores with a backup, from this or another pad
+    Restore {
+        file: PathBuf,
+    },
+    /// Wipe everything the pad stores and go back to the firmware's defaults
+    FactoryReset,
+    /// Show the analog supply, die temperature and how far each key's resting reading drifted
+    Telemetry,
+    /// Measure how often the pad reads each key
+    Benchmark,
+    /// Show how well the pad keeps its scan rate, and start measuring again
+    ScanStats,
+    /// Show the size of the pad and the resolution it reads keys at
+    Info,
+    /// Time how long a press simulated on the pad takes to reach this host as a report
+    Latency {
+        /// Key to press, has to produce a report in the active profile
+        #[arg(long, default_value_t = 0)]
+        key: u8,
+        /// Presses to time, each released again
+        #[arg(long, default_value_t = 100)]
+        rounds: usize,
+    },
+    /// Print how far each key is pressed, as applications read it
+    Analog,
+}
 
 fn main() {
-    let device = get_keyboard().unwrap();
+    let cli = Cli::parse();
+    let context = Context::new().unwrap();
+
+    match cli.command {
+        Command::Ping => ping(&context),
+        Command::Monitor => monitor(context),
+        Command::Daemon { rules } => {
+            let rules = Rules::load(&rules).unwrap_or_else(|e| panic!("{e}"));
+            // xprop needs to reach the X server from the escalated process
+            sudo::with_env(&["DISPLAY", "XAUTHORITY"]).unwrap();
+            daemon::run(Session::new(context), X11FocusSource, rules);
+        }
+        Command::Apply { file } => {
+            let wanted = load_config(&file);
+            let kb = open_keyboard(&context);
+            let changes = diff(&settings::read(&kb), &wanted);
+            for change in &changes {
+                println!("{change}");
+            }
+            settings::write(&kb, &changes);
+            println!("Applied {} changes", changes.len());
+            if changes.iter().any(|c| matches!(c, Change::Usb { .. })) {
+                println!("Pad is enumerating again with the new USB settings");
+            }
+        }
+        Command::Dump { file } => {
+            let kb = open_keyboard(&context);
+            let config = PadConfig::from_settings(&settings::read(&kb));
+            let text = toml::to_string(&config).unwrap();
+            match file {
+                Some(file) => fs::write(file, text).unwrap(),
+                None => print!("{text}"),
+            }
+        }
+        Command::Diff { file } => {
+            let wanted = load_config(&file);
+            let kb = open_keyboard(&context);
+            for change in diff(&settings::read(&kb), &wanted) {
+                println!("{change}");
+            }
+        }
+        Command::Backup { file } => {
+            let kb = open_keyboard(&context);
+            let image = settings::backup(&kb);
+            fs::write(&file, &image).unwrap();
+            println!("Saved {} bytes to {}", image.len(), file.display());
+        }
+        Command::Restore { file } => {
+            let image = fs::read(&file).unwrap_or_else(|e| panic!("Reading {}: {e}", file.display()));
+            let kb = open_keyboard(&context);
+            settings::restore(&kb, &image);
+            println!("Restored {}", file.display());
+        }
+        Command::Telemetry => {
+            let kb = open_keyboard(&context);
+            match kb.request(&Message::GetSupply).unwrap() {
+                Message::Supply { vdda_mv, temperature } => {
+                    println!("supply {vdda_mv}mV, die at {:.1}°C", temperature as f32 / 100.0);
+                }
+                msg => panic!("Unexpected answer {msg:?}"),
+            }
+            for key in 0..KEY_COUNT as u8 {
+                match kb.request(&Message::GetDrift(key)).unwrap() {
+                    Message::Drift { baseline, offset, limited, .. } => {
+                        let limited = if limited { " (limited)" } else { "" };
+                        println!("key {key}: rest {baseline}, thresholds shifted by {offset}{limited}");
+                    }
+                    msg => panic!("Unexpected answer {msg:?}"),
+                }
+            }
+        }
+        Command::Benchmark => {
+            let kb = open_keyboard(&context);
+            match kb.request(&Message::Benchmark).unwrap() {
+                Message::ScanRate { scans_per_second } => {
+                    let scan_us = 1e6 / scans_per_second.max(1) as f64;
+                    println!("every key read {scans_per_second} times a second, {scan_us:.1}us per scan");
+                }
+                msg => panic!("Unexpected answer {msg:?}"),
+            }
+        }
+        Command::ScanStats => {
+            let kb = open_keyboard(&context);
+            match kb.request(&Message::GetScanStats).unwrap() {
+                Message::ScanStats { scans, missed, min, avg, max, avg_jitter, max_jitter, max_delay } => {
+                    println!("{scans} scans, {missed} ticks missed");
+                    println!("scan took {min}/{avg}/{max}us min/avg/max");
+                    println!("started {avg_jitter}us late on average, {max_jitter}us at worst");
+                    println!("reports queued for USB {max_delay}us after the tick at worst");
+                }
+                msg => panic!("Unexpected answer {msg:?}"),
+            }
+            settings::expect_ack(&kb, &Message::ResetScanStats);
+        }
+        Command::Info => {
+            let kb = open_keyboard(&context);
+            match kb.request(&Message::GetDeviceInfo).unwrap() {
+                Message::DeviceInfo { keys, layers, profiles, bits, samples } => {
+                    println!("{keys} keys, {layers} layers, {profiles} profiles");
+                    println!("readings are {bits} bits, from {samples} conversions each");
+                }
+                msg => panic!("Unexpected answer {msg:?}"),
+            }
+        }
+        Command::Latency { key, rounds } => {
+            let kb = open_keyboard(&context);
+            let poll_ms = match kb.request(&Message::GetUsbConfig).unwrap() {
+                Message::UsbConfig(usb) => usb.poll_ms,
+                msg => panic!("Unexpected answer {msg:?}"),
+            };
+            let device = find_keyboard(&context).expect("No keyboard found");
+            let reports = ReportReader::open(&device).unwrap();
+
+            let samples = latency::measure(&kb, &reports, key, rounds.max(1));
+            let ms = |d: &Duration| d.as_secs_f64() * 1000.0;
+            let avg = samples.iter().map(ms).sum::<f64>() / samples.len().max(1) as f64;
+            println!("{} reports, polled every {poll_ms}ms", samples.len());
+            println!(
+                "{:.2}/{:.2}/{avg:.2}/{:.2}ms min/median/avg/max",
+                ms(&samples[0]),
+                ms(&samples[samples.len() / 2]),
+                ms(&samples[samples.len() - 1]),
+            );
+        }
+        Command::Analog => {
+            let kb = open_keyboard(&context);
+            let mut analog = kb.analog_reader().unwrap_or_else(|e| panic!("Opening the analog interface: {e}"));
+            loop {
+                let depths: Vec<String> = (0..KEY_COUNT)
+                    .map(|key| format!("{:5.3}", analog.read_analog(key).unwrap()))
+                    .collect();
+                println!("{}", depths.join(" "));
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        }
+        Command::FactoryReset => {
+            let kb = open_keyboard(&context);
+            match kb.request(&Message::FactoryReset).unwrap() {
+                Message::Ack => println!("Pad is back to its defaults"),
+                msg => panic!("Factory reset failed: {msg:?}"),
+            }
+        }
+    }
+}
+
+fn ping(context: &Context) {
+    let device = find_keyboard(context).expect("No keyboard found");
     println!("Found keyboard! Bus {:03} Device {:03}", device.bus_number(), device.address());
 
-    sudo::escalate_if_needed().unwrap();
     println!("Escalating to sudo to set device permissions");
-    let f = fs::File::open(format!("/dev/bus/usb/{:03}/{:03}", device.bus_number(), device.address())).unwrap();
-    let mut perms = f.metadata().unwrap().permissions();
-    perms.set_mode(0o0666);
-    f.set_permissions(perms).unwrap();
-
-    let kb = device.open().unwrap();
-    kb.claim_interface(2).unwrap();
-    kb.write_bulk(2, Message::Ping.serialize().as_slice(), Duration::from_secs(1)).unwrap();
-    let mut buf = [0; 128];
-    let len = kb.read_bulk(130, &mut buf, Duration::from_secs(0)).unwrap();
-    dbg!(Message::deserialize(&buf[..len]));
+    grant_permissions(&device);
+
+    let kb = KeyboardHandle::open(&device).unwrap();
+    kb.send(&Message::Ping).unwrap();
+    dbg!(kb.receive(Duration::from_secs(0)).unwrap());
+}
+
+fn open_keyboard(context: &Context) -> KeyboardHandle {
+    let device = find_keyboard(context).expect("No keyboard found");
+    grant_permissions(&device);
+    let kb = KeyboardHandle::open(&device).unwrap();
+    settings::check_device(&kb);
+    kb
+}
+
+fn load_config(path: &Path) -> Settings {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| panic!("Reading {}: {e}", path.display()));
+    let config: PadConfig = toml::from_str(&text).unwrap_or_else(|e| panic!("Parsing {}: {e}", path.display()));
+    config.to_settings().unwrap_or_else(|e| panic!("{}: {e}", path.display()))
 }
 
-fn get_keyboard() -> Option<Device<GlobalContext>> {
-    rusb::devices()
-        .unwrap()
-        .iter()
-        .find(|e|e.device_descriptor().unwrap().vendor_id() == VENDOR_ID )
-}
\ No newline at end of file
+fn monitor(context: Context) {
+    sudo::escalate_if_needed().unwrap();
+
+    let mut session = Session::new(context);
+    session.subscribe(Stream::KeyEvents);
+
+    println!("Waiting for keyboard");
+    loop {
+        match session.next_event() {
+            SessionEvent::Connected { bus, address } => {
+                println!("Connected: Bus {bus:03} Device {address:03}");
+            }
+            SessionEvent::Disconnected => println!("Disconnected"),
+            SessionEvent::Message(Message::KeyEvent { key, pressed }) => {
+                println!("key {key} {}", if pressed { "pressed" } else { "released" });
+            }
+            SessionEvent::Message(msg) => println!("{msg:?}"),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+use rusb::{Context, Device};
+use shared::message::{Message, Stream};
+use crate::hotplug::{watch, HotplugEvent};
+use crate::kb_handle::{grant_permissions, KeyboardHandle};
+
+/// How long a read blocks before hotplug events are looked at again.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub enum SessionEvent {
+    Connected {
+        bus: u8,
+        address: u8,
+    },
+    Disconnected,
+    Message(Message),
+}
+
+/// Connection to the pad that survives it being unplugged.
+///
+/// Whenever the pad comes back the session reattaches and subscribes to all of its streams again.
+pub struct Session {
+    hotplug: Receiver<HotplugEvent>,
+    keyboard: Option<KeyboardHandle>,
+    streams: Vec<Stream>,
+}
+
+impl Session {
+    pub fn new(context: Context) -> Self {
+        Self {
+            hotplug: watch(context),
+            keyboard: None,
+            streams: Vec::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self, stream: Stream) {
+        if !self.streams.contains(&stream) {
+            self.streams.push(stream);
+        }
+        self.send(&Message::Subscribe(stream));
+    }
+
+    /// Blocks until something happens to the pad.
+    pub fn next_event(&mut self) -> SessionEvent {
+        loop {
+            if let Some(event) = self.poll_event() {
+                return event;
+            }
+        }
+    }
+
+    /// Like [`Session::next_event`] but gives up after a short while.
+    pub fn poll_event(&mut self) -> Option<SessionEvent> {
+        let hotplug = if self.keyboard.is_some() {
+            match self.hotplug.try_recv() {
+                Ok(event) => Some(event),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => panic!("Hotplug watcher stopped"),
+            }
+        } else {
+            match self.hotplug.recv_timeout(READ_TIMEOUT) {
+                Ok(event) => Some(event),
+                Err(RecvTimeoutError::Timeout) => return None,
+                Err(RecvTimeoutError::Disconnected) => panic!("Hotplug watcher stopped"),
+            }
+        };
+
+        match hotplug {
+            Some(HotplugEvent::Arrived(device)) => return self.attach(&device),
+            Some(HotplugEvent::Left) => {
+                return self.keyboard.take().map(|_| SessionEvent::Disconnected);
+            }
+            None => {}
+        }
+
+        let kb = self.keyboard.as_ref()?;
+        match kb.receive(READ_TIMEOUT) {
+            Ok(msg) => Some(SessionEvent::Message(msg)),
+            Err(rusb::Error::Timeout) => None,
+            // The hotplug event may lag behind the failing transfer
+            Err(rusb::Error::NoDevice | rusb::Error::Io | rusb::Error::Pipe) => {
+                self.keyboard = None;
+                Some(SessionEvent::Disconnected)
+            }
+            Err(e) => panic!("Reading from keyboard failed: {e}"),
+        }
+    }
+
+    /// Sends a message if the pad is currently attached.
+    pub fn send(&self, msg: &Message) -> bool {
+        match &self.keyboard {
+            Some(kb) => kb.send(msg).is_ok(),
+            None => false,
+        }
+    }
+
+    fn attach(&mut self, device: &Device<Context>) -> Option<SessionEvent> {
+        grant_permissions(device);
+
+        let kb = match KeyboardHandle::open(device) {
+            Ok(kb) => kb,
+            Err(e) => {
+                eprintln!("Failed to open keyboard: {e}");
+                return None;
+            }
+        };
+
+        for stream in &self.streams {
+            if let Err(e) = kb.send(&Message::Subscribe(*stream)) {
+                eprintln!("Failed to subscribe to {stream:?}: {e}");
+                return None;
+            }
+        }
+        self.keyboard = Some(kb);
+
+        Some(SessionEvent::Connected {
+            bus: device.bus_number(),
+            address: device.address(),
+        })
+    }
+}
@@ -0,0 +1,169 @@
+use shared::file::Change;
+use shared::keymap::{KEY_COUNT, LAYER_COUNT, PROFILE_COUNT};
+use shared::message::{Message, IMAGE_CHUNK_LEN};
+use shared::settings::{encode_name, Settings};
+use shared::store::{self, Header, HEADER_LEN};
+use crate::kb_handle::KeyboardHandle;
+
+/// Makes sure the pad has the shape this CLI was built for.
+pub fn check_device(kb: &KeyboardHandle) {
+    match kb.request(&Message::GetDeviceInfo).unwrap() {
+        Message::DeviceInfo { keys, layers, profiles, .. }
+            if (keys as usize, layers as usize, profiles as usize) == (KEY_COUNT, LAYER_COUNT, PROFILE_COUNT) => {}
+        Message::DeviceInfo { keys, layers, profiles, .. } => panic!(
+            "Pad has {keys} keys, {layers} layers and {profiles} profiles, expected {KEY_COUNT}, {LAYER_COUNT} and {PROFILE_COUNT}"
+        ),
+        msg => panic!("Unexpected answer {msg:?}"),
+    }
+}
+
+/// Reads the running settings from the pad.
+pub fn read(kb: &KeyboardHandle) -> Settings {
+    let mut settings = Settings::empty();
+
+    for (p, profile) in settings.profiles.iter_mut().enumerate() {
+        match kb.request(&Message::GetProfileName(p as u8)).unwrap() {
+            Message::ProfileName { name, .. } => profile.name = name,
+            msg => panic!("Unexpected answer {msg:?}"),
+        }
+        for (l, layer) in profile.keymap.iter_mut().enumerate() {
+            for (k, action) in layer.iter_mut().enumerate() {
+                let request = Message::GetAction { profile: p as u8, layer: l as u8, key: k as u8 };
+                match kb.request(&request).unwrap() {
+                    Message::Action { action: a, .. } => *action = a,
+                    msg => panic!("Unexpected answer {msg:?}"),
+                }
+            }
+        }
+        for (k, binding) in profile.gamepad.iter_mut().enumerate() {
+            match kb.request(&Message::GetGamepadBinding { profile: p as u8, key: k as u8 }).unwrap() {
+                Message::GamepadBinding { binding: b, .. } => *binding = b,
+                msg => panic!("Unexpected answer {msg:?}"),
+            }
+        }
+        for (k, note) in profile.notes.iter_mut().enumerate() {
+            match kb.request(&Message::GetMidiNote { profile: p as u8, key: k as u8 }).unwrap() {
+                Message::MidiNote { note: n, .. } => *note = n,
+                msg => panic!("Unexpected answer {msg:?}"),
+            }
+        }
+        match kb.request(&Message::GetMidiConfig(p as u8)).unwrap() {
+            Message::MidiConfig { config, .. } => profile.midi = config,
+            msg => panic!("Unexpected answer {msg:?}"),
+        }
+    }
+
+    for k in 0..KEY_COUNT {
+        match kb.request(&Message::GetKeyConfig(k as u8)).unwrap() {
+            Message::KeyConfig { config, .. } => settings.keys[k] = config,
+            msg => panic!("Unexpected answer {msg:?}"),
+        }
+        match kb.request(&Message::GetCalibration(k as u8)).unwrap() {
+            Message::Calibration { calibration, .. } => settings.calibration[k] = calibration,
+            msg => panic!("Unexpected answer {msg:?}"),
+        }
+    }
+
+    match kb.request(&Message::GetScanConfig).unwrap() {
+        Message::ScanConfig(scan) => settings.scan = scan,
+        msg => panic!("Unexpected answer {msg:?}"),
+    }
+    match kb.request(&Message::GetUsbConfig).unwrap() {
+        Message::UsbConfig(usb) => settings.usb = usb,
+        msg => panic!("Unexpected answer {msg:?}"),
+    }
+    match kb.request(&Message::GetMouseConfig).unwrap() {
+        Message::MouseConfig(mouse) => settings.mouse = mouse,
+        msg => panic!("Unexpected answer {msg:?}"),
+    }
+
+    settings
+}
+
+/// Applies the changes to the pad and persists the result.
+///
+/// The pad enumerates again afterwards if the USB settings changed, dropping `kb`'s connection.
+pub fn write(kb: &KeyboardHandle, changes: &[Change]) {
+    for change in changes {
+        let request = match change {
+            Change::ProfileName { profile, to, .. } => Message::SetProfileName {
+                profile: *profile as u8,
+                name: encode_name(to),
+            },
+            Change::Action { profile, layer, key, to, .. } => Message::SetAction {
+                profile: *profile as u8,
+                layer: *layer as u8,
+                key: *key as u8,
+                action: *to,
+            },
+            Change::GamepadBinding { profile, key, to, .. } => Message::SetGamepadBinding {
+                profile: *profile as u8,
+                key: *key as u8,
+                binding: *to,
+            },
+            Change::Midi { profile, to, .. } => Message::SetMidiConfig { profile: *profile as u8, config: *to },
+            Change::Note { profile, key, to, .. } => Message::SetMidiNote {
+                profile: *profile as u8,
+                key: *key as u8,
+                note: *to,
+            },
+            Change::KeyConfig { key, to, .. } => Message::SetKeyConfig { key: *key as u8, config: *to },
+            Change::Calibration { key, to, .. } => Message::SetCalibration { key: *key as u8, calibration: *to },
+            Change::Scan { to, .. } => Message::SetScanConfig(*to),
+            Change::Usb { to, .. } => Message::SetUsbConfig(*to),
+            Change::Mouse { to, .. } => Message::SetMouseConfig(*to),
+        };
+        expect_ack(kb, &request);
+    }
+    expect_ack(kb, &Message::SaveSettings);
+}
+
+/// Reads the complete device state as a `shared::store` image.
+pub fn backup(kb: &KeyboardHandle) -> Vec<u8> {
+    let header = match kb.request(&Message::BeginBackup).unwrap() {
+        Message::ImageInfo { version, len, crc } => Header { version, len, crc, sequence: 0 },
+        msg => panic!("Pad refused backup: {msg:?}"),
+    };
+
+    let mut image = header.to_bytes().to_vec();
+    while image.len() < header.image_len() {
+        let offset = (image.len() - HEADER_LEN) as u16;
+        match kb.request(&Message::ReadImage(offset)).unwrap() {
+            Message::ImageChunk { offset: o, len, data } if o == offset && len > 0 => {
+                image.extend_from_slice(&data[..len as usize]);
+            }
+            msg => panic!("Unexpected answer {msg:?}"),
+        }
+    }
+
+    if let Err(e) = store::decode(&image) {
+        panic!("Backup read from pad is broken: {e:?}");
+    }
+    image
+}
+
+/// Replaces the complete device state with an image made by [`backup`].
+///
+/// The pad only takes the image over once it arrived completely and checks out.
+pub fn restore(kb: &KeyboardHandle, image: &[u8]) {
+    let (header, _) = store::decode(image).unwrap_or_else(|e| panic!("Backup is broken: {e:?}"));
+
+    expect_ack(kb, &Message::BeginRestore { version: header.version, len: header.len, crc: header.crc });
+    for (i, chunk) in image[HEADER_LEN..header.image_len()].chunks(IMAGE_CHUNK_LEN).enumerate() {
+        let mut data = [0; IMAGE_CHUNK_LEN];
+        data[..chunk.len()].copy_from_slice(chunk);
+        expect_ack(kb, &Message::ImageChunk {
+            offset: (i * IMAGE_CHUNK_LEN) as u16,
+            len: chunk.len() as u8,
+            data,
+        });
+    }
+    expect_ack(kb, &Message::CommitRestore);
+}
+
+pub fn expect_ack(kb: &KeyboardHandle, request: &Message) {
+    match kb.request(request).unwrap() {
+        Message::Ack => {}
+        msg => panic!("Pad refused {request:?}: {msg:?}"),
+    }
+}
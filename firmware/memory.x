@@ -1,9 +1,10 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 512K - 128K
+  FLASH : ORIGIN = 0x08000000, LENGTH = 512K - 256K
   /* Changes made here must be reflected in constants.rs */
-  USER_FLASH :  ORIGIN = 0x08060000, LENGTH = 128K
+  /* Sectors 6 and 7, one settings slot each */
+  USER_FLASH :  ORIGIN = 0x08040000, LENGTH = 256K
   RAM : ORIGIN = 0x20000000, LENGTH = 128K
 }
 
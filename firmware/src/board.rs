@@ -0,0 +1,22 @@
+//! How this PCB wires its hall sensors, the file to change for another board.
+//!
+//! Along with `ROWS` and `COLS` in `shared::keymap`, which the CLI and the plugin are built with too.
+
+use shared::matrix::{sensors, MuxMap, Sensor, MUX_POSITIONS};
+use shared::keymap::KEY_COUNT;
+
+/// 16 channel multiplexers, each on its own ADC input, see `main` for the pins.
+pub const MUX_COUNT: usize = 1;
+
+/// Layout `(row, col)` of each multiplexer channel.
+const MUX_MAP: MuxMap<MUX_COUNT> = {
+	let mut map = [[None; MUX_POSITIONS]; MUX_COUNT];
+	map[0][0] = Some((0, 0));
+	map[0][1] = Some((0, 1));
+	map[0][2] = Some((0, 2));
+	map[0][3] = Some((0, 3));
+	map
+};
+
+/// Sensor of each key in the layout, checked while compiling.
+pub const SENSORS: [Option<Sensor>; KEY_COUNT] = sensors(&MUX_MAP);
@@ -1,4 +1,9 @@
-
-
 /// DO NOT MODIFY UNLESS memory.x is changed!
-pub const FLASH_OFFSET: u32 = 0x08060000 - 0x08000000;
\ No newline at end of file
+pub const FLASH_OFFSET: u32 = 0x08040000 - 0x08000000;
+/// Settings alternate between slots, each one erase sector large.
+pub const SLOT_SIZE: u32 = 128 * 1024;
+pub const SLOT_COUNT: u32 = 2;
+/// Factory calibration of the internal ADC channels in system memory, see the datasheet's "Temperature sensor and VREFINT calibration values".
+pub const VREFINT_CAL: usize = 0x1FFF7A2A;
+pub const TS_CAL1: usize = 0x1FFF7A2C;
+pub const TS_CAL2: usize = 0x1FFF7A2E;
@@ -0,0 +1,56 @@
+//! Lock free queue from the scanner's interrupt executor to the USB task.
+//!
+//! Neither side ever waits for the other, so USB can't hold up a scan.
+
+use core::future::poll_fn;
+use core::task::Poll;
+use embassy_sync::waitqueue::AtomicWaker;
+use heapless::spsc::{Consumer, Producer, Queue};
+
+/// Holds `N - 1` values.
+pub type HandoffQueue<T, const N: usize> = Queue<T, N>;
+
+pub struct Sender<T: 'static, const N: usize> {
+	producer: Producer<'static, T, N>,
+	waker: &'static AtomicWaker,
+}
+
+pub struct Receiver<T: 'static, const N: usize> {
+	consumer: Consumer<'static, T, N>,
+	waker: &'static AtomicWaker,
+}
+
+pub fn channel<T, const N: usize>(
+	queue: &'static mut HandoffQueue<T, N>,
+	waker: &'static AtomicWaker,
+) -> (Sender<T, N>, Receiver<T, N>) {
+	let (producer, consumer) = queue.split();
+	(Sender { producer, waker }, Receiver { consumer, waker })
+}
+
+impl<T, const N: usize> Sender<T, N> {
+	/// Whether there is room for another value.
+	pub fn ready(&self) -> bool {
+		self.producer.ready()
+	}
+
+	/// Gives the value back when the queue is full.
+	pub fn try_send(&mut self, value: T) -> Result<(), T> {
+		self.producer.enqueue(value)?;
+		self.waker.wake();
+		Ok(())
+	}
+}
+
+impl<T, const N: usize> Receiver<T, N> {
+	pub async fn receive(&mut self) -> T {
+		poll_fn(|cx| {
+			// Registered before looking, so a value queued in between still wakes us
+			self.waker.register(cx.waker());
+			match self.consumer.dequeue() {
+				Some(value) => Poll::Ready(value),
+				None => Poll::Pending,
+			}
+		}).await
+	}
+}
@@ -0,0 +1,28 @@
+//! Lock states the host keeps, for actions to read and the board to show.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use embassy_stm32::gpio::{Level, Output};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use shared::report::Leds;
+
+static LEDS: AtomicU8 = AtomicU8::new(0);
+static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn set_leds(leds: Leds) {
+	LEDS.store(leds.0, Ordering::Relaxed);
+	CHANGED.signal(());
+}
+
+pub fn leds() -> Leds {
+	Leds(LEDS.load(Ordering::Relaxed))
+}
+
+/// Lights the board's LED, wired active low, while caps lock is on.
+#[embassy_executor::task]
+pub async fn show(mut led: Output<'static>) {
+	loop {
+		led.set_level(if leds().caps_lock() { Level::Low } else { Level::High });
+		CHANGED.wait().await;
+	}
+}
@@ -3,36 +3,74 @@
 
 use core::default::Default;
 
-use defmt::info;
+use defmt::{error, info, warn};
 use embassy_executor::Spawner;
-use embassy_futures::join::{join, join3};
 use embassy_stm32::{bind_interrupts, Config, Peripheral};
-use embassy_stm32::adc::{Adc, AdcChannel, AnyAdcChannel};
+use embassy_stm32::adc::{Adc, AdcChannel};
 use embassy_stm32::exti::Channel as AnyChannel;
 use embassy_stm32::flash::{Flash, InterruptHandler as FInterruptHandler};
-use embassy_stm32::flash::Bank1Region3;
-use embassy_stm32::gpio::{AnyPin, Level, Output, Pin, Speed};
-use embassy_stm32::peripherals::ADC1;
+use embassy_stm32::gpio::{Level, Output, Pin, Speed};
 use embassy_stm32::time::Hertz;
-use embassy_sync::blocking_mutex::raw::NoopRawMutex;
-use embassy_sync::channel::Channel;
-use embassy_time::Timer;
-use keyberon::action::Action;
-use keyberon::key_code::{KbHidReport, KeyCode};
-use keyberon::layout::{Event, Layout};
+use embassy_executor::InterruptExecutor;
+use embassy_stm32::interrupt;
+use embassy_stm32::interrupt::{InterruptExt, Priority};
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use shared::config::{full_scale, scale, KeyConfig, KeyMode, ScanConfig, REFERENCE_BITS};
+use shared::key::KeyState;
+use shared::keycode::usage;
+use shared::keymap::{Action, Layout, KEY_COUNT};
+use shared::message::Message;
+use shared::gamepad;
+use shared::midi::{MidiEvent, MidiKeys};
+use shared::mouse::MouseKeys;
+use shared::report::{AnalogReport, ConsumerReport, GamepadReport, MouseReport, NkroReport};
+use shared::settings::{Profile, Settings};
+use shared::stats::ScanStats;
+use shared::matrix::Sensor;
+use crate::board::{MUX_COUNT, SENSORS};
+use crate::handoff::HandoffQueue;
+use crate::reader::AnalogueReader;
 use crate::usb::setup_usb;
 
 mod usb;
 mod util;
 mod constants;
 mod hid;
+mod profile;
+mod settings;
+mod telemetry;
+mod reader;
+mod board;
+mod power;
+mod handoff;
+mod simulate;
+mod indicators;
 
 bind_interrupts!(struct Irqs {
     FLASH => FInterruptHandler;
 });
 
+/// Runs the scanner above everything on the thread executor, on an interrupt no peripheral uses.
+static SCAN_EXECUTOR: InterruptExecutor = InterruptExecutor::new();
+
+#[interrupt]
+unsafe fn USART6() {
+    SCAN_EXECUTOR.on_interrupt()
+}
+
+/// Queue lengths from the scanner to the USB task, holding one less than this.
+pub const REPORT_QUEUE_LEN: usize = 16;
+pub const EVENT_QUEUE_LEN: usize = 16;
+pub const CONSUMER_QUEUE_LEN: usize = 4;
+pub const MOUSE_QUEUE_LEN: usize = 4;
+pub const GAMEPAD_QUEUE_LEN: usize = 4;
+pub const ANALOG_QUEUE_LEN: usize = 4;
+/// Every note on needs its note off, so this holds a press and release of every key.
+pub const MIDI_QUEUE_LEN: usize = 2 * KEY_COUNT + 1;
+
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
+async fn main(spawner: Spawner) {
     let mut config = Config::default();
 
     {
@@ -57,209 +95,416 @@ async fn main(_spawner: Spawner) {
 
     let p = embassy_stm32::init(config);
 
-    let channel: Channel<NoopRawMutex, KbHidReport, 10> = Channel::new();
-    let sender = channel.sender();
-    let usb = setup_usb(p.USB_OTG_FS, channel.receiver(), p.PA12, p.PA11);
-
-    let flash = Flash::new(p.FLASH, Irqs);
-    let regions = flash.into_regions();
-    let mut user_flash: Bank1Region3 = regions.bank1_region3;
+    let (report_sender, report_receiver) = handoff::channel(
+        make_static!(HandoffQueue<NkroReport, REPORT_QUEUE_LEN>, HandoffQueue::new()),
+        make_static!(AtomicWaker, AtomicWaker::new()),
+    );
+    let (consumer_sender, consumer_receiver) = handoff::channel(
+        make_static!(HandoffQueue<ConsumerReport, CONSUMER_QUEUE_LEN>, HandoffQueue::new()),
+        make_static!(AtomicWaker, AtomicWaker::new()),
+    );
+    let (mouse_sender, mouse_receiver) = handoff::channel(
+        make_static!(HandoffQueue<MouseReport, MOUSE_QUEUE_LEN>, HandoffQueue::new()),
+        make_static!(AtomicWaker, AtomicWaker::new()),
+    );
+    let (gamepad_sender, gamepad_receiver) = handoff::channel(
+        make_static!(HandoffQueue<GamepadReport, GAMEPAD_QUEUE_LEN>, HandoffQueue::new()),
+        make_static!(AtomicWaker, AtomicWaker::new()),
+    );
+    let (analog_sender, analog_receiver) = handoff::channel(
+        make_static!(HandoffQueue<AnalogReport, ANALOG_QUEUE_LEN>, HandoffQueue::new()),
+        make_static!(AtomicWaker, AtomicWaker::new()),
+    );
+    let (midi_sender, midi_receiver) = handoff::channel(
+        make_static!(HandoffQueue<MidiEvent, MIDI_QUEUE_LEN>, HandoffQueue::new()),
+        make_static!(AtomicWaker, AtomicWaker::new()),
+    );
+    // Messages pushed to the host unsolicited, dropped by the USB task when nobody subscribed to them
+    let (event_sender, event_receiver) = handoff::channel(
+        make_static!(HandoffQueue<Message, EVENT_QUEUE_LEN>, HandoffQueue::new()),
+        make_static!(AtomicWaker, AtomicWaker::new()),
+    );
+    let usb = setup_usb(
+        p.USB_OTG_FS,
+        report_receiver,
+        consumer_receiver,
+        mouse_receiver,
+        gamepad_receiver,
+        analog_receiver,
+        midi_receiver,
+        event_receiver,
+        p.PA12,
+        p.PA11,
+    );
 
-    let mut reader = AnalogueReader::new(
+    let mut reader = AnalogueReader::<MUX_COUNT>::new(
         p.PA5.degrade(),
         p.PA4.degrade(),
         p.PA3.degrade(),
         p.PA2.degrade(),
-        p.PA1.degrade(),
+        // One enable pin and ADC input per multiplexer
+        [p.PA1.degrade()],
         [p.PA6.degrade_adc()],
         Adc::new(p.ADC1),
+        p.TIM1,
+        p.DMA2_CH5,
+        p.DMA2_CH0,
     );
 
-    let scanner = async {
-        let mut layout = Layout::new(&[
-            &[
-                &[
-                    Action::KeyCode(KeyCode::A),
-                    Action::KeyCode(KeyCode::S),
-                    Action::KeyCode(KeyCode::D),
-                    Action::KeyCode(KeyCode::W),
-                ]
-            ]
-        ]);
-
-        let mut keys = AnalogueMatrix::new([KeyConfig::Threshold(1500); 4]);
-
-        let mut previous_report = None;
-
-        loop {
-            for (x, pressed) in keys.get(&mut reader) {
-                let _ = layout.event(if pressed {
-                    Event::Press(0, x as u8)
-                } else {
-                    Event::Release(0, x as u8)
-                });
+    // The black pill's LED, on PC13
+    let led = Output::new(p.PC13.degrade(), Level::High, Speed::Low);
+
+    let flash = Flash::new(p.FLASH, Irqs);
+    settings::init(flash, default_settings).await;
+
+    if reset_key_held(&mut reader).await {
+        warn!("Reset key held while plugging in, restoring defaults");
+        if let Err(e) = settings::factory_reset().await {
+            error!("Factory reset failed: {}", e);
+        }
+    }
+
+    // Sector erases still stall every flash read, scans pause for those
+    interrupt::USART6.set_priority(Priority::P6);
+    let scan_spawner = SCAN_EXECUTOR.start(interrupt::USART6);
+    scan_spawner.must_spawn(scanner(
+        reader,
+        report_sender,
+        consumer_sender,
+        mouse_sender,
+        gamepad_sender,
+        analog_sender,
+        midi_sender,
+        event_sender,
+    ));
+    spawner.must_spawn(indicators::show(led));
+
+    usb.await;
+}
+
+/// Scans the keys and resolves them into reports, on the interrupt executor so USB can't delay it.
+#[embassy_executor::task]
+async fn scanner(
+    mut reader: AnalogueReader<MUX_COUNT>,
+    mut reports: handoff::Sender<NkroReport, REPORT_QUEUE_LEN>,
+    mut consumer_reports: handoff::Sender<ConsumerReport, CONSUMER_QUEUE_LEN>,
+    mut mouse_reports: handoff::Sender<MouseReport, MOUSE_QUEUE_LEN>,
+    mut gamepad_reports: handoff::Sender<GamepadReport, GAMEPAD_QUEUE_LEN>,
+    mut analog_reports: handoff::Sender<AnalogReport, ANALOG_QUEUE_LEN>,
+    mut midi_events: handoff::Sender<MidiEvent, MIDI_QUEUE_LEN>,
+    mut events: handoff::Sender<Message, EVENT_QUEUE_LEN>,
+) {
+    let mut active_profile = profile::active();
+    let mut generation = settings::generation();
+    let mut layout = Layout::new();
+
+    let mut keys = AnalogueMatrix::<KEY_COUNT>::new(SENSORS);
+    let mut scan = settings::with(|s| {
+        keys.configure(s);
+        reader.configure(s.scan);
+        s.scan
+    });
+
+    let mut previous_report = NkroReport::new();
+    let mut previous_consumer = ConsumerReport::default();
+    let mut mouse = MouseKeys::new();
+    let mut previous_gamepad = GamepadReport::default();
+    let mut previous_analog = AnalogReport::new();
+    let mut midi = MidiKeys::new();
+    let mut previous_scan = Instant::now();
+    let mut supply_sampled = Instant::now();
+    let mut last_moved = Instant::now();
+    let mut pace = Pace::new();
+
+    loop {
+        if power::suspended() {
+            // The muxes are off between scans, so there is nothing left to switch off
+            power::resumed().await;
+            last_moved = Instant::now();
+            previous_scan = Instant::now();
+            pace.restart();
+        }
+
+        if profile::active() != active_profile {
+            active_profile = profile::active();
+            info!("Switching to profile {}", active_profile);
+            // Release everything resolved against the old keymap
+            layout.clear();
+            midi.release_all(|event| {
+                let _ = midi_events.try_send(event);
+            });
+        }
+        if settings::generation() != generation {
+            generation = settings::generation();
+            let previous = scan;
+            scan = settings::with(|s| {
+                keys.configure(s);
+                reader.configure(s.scan);
+                s.scan
+            });
+            if scan != previous {
+                telemetry::update(|t| t.stats = ScanStats::new());
             }
-            //
-            // let _ = layout.tick();
+        }
 
-            if let Some(ref prev_report) = previous_report {
-                let report: KbHidReport = layout.tick().collect();
+        let idle = scan.idle_after_s != 0 && last_moved.elapsed() >= Duration::from_secs(scan.idle_after_s as u64);
+        pace.set_period(scan_period(&scan, idle));
+        let tick = pace.wait().await;
+        let started = Instant::now();
+        let since_previous = started.duration_since(previous_scan).as_micros() as u32;
+        previous_scan = started;
 
-                if *prev_report != report {
-                    previous_report = Some(report.clone());
-                    sender.send(report).await;
-                }
-            } else {
-                let report: KbHidReport = layout.tick().collect();
-                previous_report = Some(report);
+        for (x, pressed) in keys.get(&mut reader).await {
+            if pressed {
+                settings::count_press(x);
             }
+            // Never stall the scanner for the host, a full queue just loses events
+            let _ = events.try_send(Message::KeyEvent { key: x as u8, pressed });
+            settings::with(|s| layout.event(&s.profiles[active_profile as usize].keymap, x, pressed));
+        }
+        // Not counted as presses, the host only measures with them
+        while let Some((x, pressed)) = simulate::next() {
+            settings::with(|s| layout.event(&s.profiles[active_profile as usize].keymap, x, pressed));
+        }
 
-            // info!("k1: {:?}, k2: {:?}, k3: {:?}, k4: {:?}", keys[0].pressed, keys[1].pressed, keys[2].pressed, keys[3].pressed);
+        if supply_sampled.elapsed() >= SUPPLY_INTERVAL {
+            supply_sampled = Instant::now();
+            let temperature = reader.sample_supply();
+            telemetry::update(|t| {
+                t.vdda_mv = reader.supply.vdda_mv();
+                t.temperature = temperature;
+            });
+        }
 
-            Timer::after_nanos(100).await;
+        let report: NkroReport = layout.keycodes().collect();
+        // A full queue leaves the report for the next scan to try again
+        if report != previous_report && reports.try_send(report.clone()).is_ok() {
+            previous_report = report;
+        }
+        let consumer: ConsumerReport = layout.consumer_usages().collect();
+        if consumer != previous_consumer && consumer_reports.try_send(consumer.clone()).is_ok() {
+            previous_consumer = consumer;
         }
-    };
 
-    join(usb, scanner).await;
-}
+        let held = layout.mouse_actions().map(|(x, action)| (action, keys.keys[x].travel()));
+        settings::with(|s| mouse.update(&s.mouse, held, since_previous));
+        // Movement keeps adding up while the host is behind, instead of getting lost
+        if mouse_reports.ready() {
+            if let Some(report) = mouse.report() {
+                let _ = mouse_reports.try_send(report);
+            }
+        }
 
-struct AnalogueMatrix<const SIZE: usize> {
-    keys: [KeyState; SIZE]
-}
+        // Bound keys keep their keymap action too, profiles without bindings leave the gamepad centered
+        let gamepad = settings::with(|s| {
+            let bound = s.profiles[active_profile as usize].gamepad.iter()
+                .zip(&keys.keys)
+                .filter_map(|(binding, key)| Some((binding.as_ref()?, key.travel(), key.pressed)));
+            gamepad::report(bound)
+        });
+        if gamepad != previous_gamepad && gamepad_reports.try_send(gamepad.clone()).is_ok() {
+            previous_gamepad = gamepad;
+        }
+        let now_us = started.as_micros() as u32;
+        settings::with(|s| {
+            let profile = &s.profiles[active_profile as usize];
+            let keys = keys.keys.iter().map(|key| (key.travel(), key.pressed));
+            midi.update(&profile.midi, &profile.notes, keys, now_us, |event| {
+                // A full queue means the host stopped reading, notes are lost then anyway
+                let _ = midi_events.try_send(event);
+            });
+        });
 
-impl<const SIZE: usize> AnalogueMatrix<SIZE> {
-    fn new(keys: [KeyConfig; SIZE]) -> Self {
-        Self {
-            keys: keys.map(|c| KeyState {
-                min: u16::MAX,
-                max: u16::MIN,
-                pressed: false,
-                changed: false,
-                // ADC max
-                last: [4096; SMA_WINDOW],
-                config: c,
-            })
+        let analog = AnalogReport { travel: core::array::from_fn(|x| keys.keys[x].travel()) };
+        // Small wobbles would crowd the keys off the endpoint they share
+        if analog.moved(&previous_analog) && analog_reports.try_send(analog.clone()).is_ok() {
+            previous_analog = analog;
         }
-    }
+        let handed_off = started.elapsed().as_micros() as u32;
+
+        // info!("k1: {:?}, k2: {:?}, k3: {:?}, k4: {:?}", keys[0].pressed, keys[1].pressed, keys[2].pressed, keys[3].pressed);
 
-    fn get<'a>(&'a mut self, reader: &'a mut AnalogueReader) -> impl Iterator<Item = (usize, bool)> + 'a  {
-        for i in 0..SIZE {
-            let value = reader.sample(i);
-            self.keys[i].update(value);
+        if keys.moved {
+            last_moved = Instant::now();
         }
 
-        self.keys.iter()
-            .enumerate()
-            .filter(|k| k.1.changed)
-            .map(|k| (k.0, k.1.pressed))
+        let duration = started.elapsed().as_micros() as u32;
+        telemetry::update(|t| {
+            t.scans = t.scans.wrapping_add(1);
+            for (drift, key) in t.drift.iter_mut().zip(&keys.keys) {
+                *drift = key.drift();
+            }
+            // Idle scans are slow on purpose
+            match tick {
+                _ if idle || scan.rate_hz == 0 => {}
+                Ok(late) => {
+                    let late = late.as_micros() as u32;
+                    t.stats.record(late, duration, late + handed_off);
+                }
+                Err(missed) => {
+                    t.stats.miss(missed);
+                    // Started right away, without waiting for a tick
+                    t.stats.record(0, duration, handed_off);
+                }
+            }
+        });
     }
 }
 
-struct AnalogueReader<const AMOUNT: usize = 1> {
-    channels: [AnyAdcChannel<ADC1>; AMOUNT],
-    adc: Adc<'static, ADC1>,
-    s0: Output<'static>,
-    s1: Output<'static>,
-    s2: Output<'static>,
-    s3: Output<'static>,
-    en: Output<'static>,
+/// How often VREFINT and the temperature sensor are read, supply sag is slow next to a scan.
+const SUPPLY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Time between scans, `None` to scan back to back.
+fn scan_period(scan: &ScanConfig, idle: bool) -> Option<Duration> {
+    if idle {
+        Some(Duration::from_millis(scan.idle_interval_ms.max(1) as u64))
+    } else if scan.rate_hz == 0 {
+        None
+    } else {
+        Some(Duration::from_hz(scan.rate_hz as u64))
+    }
 }
 
-impl<const AMOUNT: usize> AnalogueReader<AMOUNT> {
-    fn new(
-        s0: AnyPin,
-        s1: AnyPin,
-        s2: AnyPin,
-        s3: AnyPin,
-        en: AnyPin,
-        mut multiplexers: [AnyAdcChannel<ADC1>; AMOUNT],
-        adc: Adc<'static, ADC1>
-    ) -> Self {
-        // low speed 8MHz
-        // medium speed 50MHz
-        // maximum high-speed 100MHz
-        // very high-speed 180Mhz.
+/// Starts scans on a fixed schedule, independent of how long each one takes.
+struct Pace {
+    ticker: Option<Ticker>,
+    period: Option<Duration>,
+    /// When the next tick is due.
+    due: Instant,
+}
 
+impl Pace {
+    fn new() -> Self {
         Self {
-            channels: multiplexers,
-            adc,
-            s0: Output::new(s0, Level::Low, Speed::Low),
-            s1: Output::new(s1, Level::Low, Speed::Low),
-            s2: Output::new(s2, Level::Low, Speed::Low),
-            s3: Output::new(s3, Level::Low, Speed::Low),
-            en: Output::new(en, Level::Low, Speed::Low),
+            ticker: None,
+            period: None,
+            due: Instant::now(),
         }
     }
 
-    fn sample(&mut self, channel: usize) -> u16 {
-        self.s0.set_level(Level::from(channel & 1 == 1));
-        self.s1.set_level(Level::from((channel >> 1) & 1 == 1));
-        self.s2.set_level(Level::from((channel >> 2) & 1 == 1));
-        self.s3.set_level(Level::from((channel >> 3) & 1 == 1));
+    fn set_period(&mut self, period: Option<Duration>) {
+        if period != self.period {
+            self.period = period;
+            self.restart();
+        }
+    }
 
-        self.adc.blocking_read(&mut self.channels[channel / 16])
+    /// Starts the schedule over from now.
+    fn restart(&mut self) {
+        self.ticker = self.period.map(Ticker::every);
+        self.due = Instant::now() + self.period.unwrap_or(Duration::MIN);
     }
-}
 
-#[derive(Copy, Clone)]
-enum  KeyConfig {
-    // distance 0 - 400 (think about bigger range)
-    Threshold(u16),
-    RappidTrigger(u16),
+    /// Waits for the next tick and returns how late it came,
+    /// or how many ticks were skipped when a scan ran into the next one.
+    async fn wait(&mut self) -> Result<Duration, u32> {
+        let (Some(ticker), Some(period)) = (&mut self.ticker, self.period) else {
+            return Ok(Duration::MIN);
+        };
+        ticker.next().await;
+
+        let now = Instant::now();
+        let late = now.saturating_duration_since(self.due);
+        if late >= period {
+            // Catching up would run a burst of back to back scans, start over instead
+            ticker.reset();
+            self.due = now + period;
+            Err((late.as_ticks() / period.as_ticks()) as u32)
+        } else {
+            self.due += period;
+            Ok(late)
+        }
+    }
 }
 
-const SMA_WINDOW: usize = 8;
-
-// TODO convert to distance
-#[derive(Copy, Clone)]
-struct KeyState {
-    pub min: u16,
-    pub max: u16,
-    pressed: bool,
-    config: KeyConfig,
-    changed: bool,
-    last: [u16; SMA_WINDOW]
+/// Holding this key while plugging in wipes all settings.
+const RESET_KEY: usize = 0;
+
+/// Checks the reset key against the default threshold, the stored one may be what got the pad bricked.
+async fn reset_key_held(reader: &mut AnalogueReader<MUX_COUNT>) -> bool {
+    const SAMPLES: u32 = 32;
+
+    let KeyMode::Threshold(threshold) = default_settings().keys[RESET_KEY].mode else {
+        return false;
+    };
+    let Some(sensor) = SENSORS[RESET_KEY] else {
+        return false;
+    };
+
+    let mut sum = 0;
+    for _ in 0..SAMPLES {
+        sum += reader.sample(sensor) as u32;
+        Timer::after_millis(1).await;
+    }
+    sum / SAMPLES < threshold.actuation as u32
 }
 
-impl KeyState {
-    fn update(&mut self, value: u16) {
-        if value > self.max {
-            self.max = value;
-        }
+/// Keymaps and key settings used until the host stores its own, or after a factory reset.
+fn default_settings() -> Settings {
+    let key = |name| Action::Key(usage(name).unwrap());
+
+    let mut settings = Settings::empty();
+    settings.profiles[0] = Profile::empty().with_name("default");
+    settings.profiles[0].keymap[0] = [key("A"), key("S"), key("D"), key("W")];
+    settings.profiles[1] = Profile::empty().with_name("game");
+    settings.profiles[1].keymap[0] = [key("Z"), key("X"), key("C"), key("V")];
+    settings.keys = [KeyConfig::default(); KEY_COUNT];
+    settings
+}
 
-        if value < self.min {
-            self.min = value;
-        }
+/// Filtered readings have to change by this much to count as a key moving, for the idle scan rate.
+/// At `REFERENCE_BITS` like all thresholds.
+const MOVE_BAND: u16 = 16;
 
-        // update sma window
-        self.last.rotate_left(1);
-        self.last[SMA_WINDOW - 1] = value;
+struct AnalogueMatrix<const SIZE: usize> {
+    keys: [KeyState; SIZE],
+    sensors: [Option<Sensor>; SIZE],
+    /// Filtered readings when each key last counted as moving.
+    still_at: [u16; SIZE],
+    /// Whether a key moved during the last scan.
+    moved: bool,
+    /// Resolution of the readings.
+    bits: u8,
+}
 
-        let avg = self.last.iter().sum::<u16>() / (SMA_WINDOW as u16);
+impl<const SIZE: usize> AnalogueMatrix<SIZE> {
+    fn new(sensors: [Option<Sensor>; SIZE]) -> Self {
+        Self {
+            keys: [KeyState::new(full_scale(REFERENCE_BITS)); SIZE],
+            sensors,
+            still_at: [full_scale(REFERENCE_BITS); SIZE],
+            moved: false,
+            bits: REFERENCE_BITS,
+        }
+    }
 
-        match self.config {
-            KeyConfig::Threshold(v) => {
-                let pressed = avg < v;
+    /// Picks up per key settings, keeping what was learned while scanning.
+    fn configure(&mut self, settings: &Settings) {
+        self.bits = settings.scan.bits();
+        for (i, key) in self.keys.iter_mut().enumerate() {
+            key.configure(settings.keys[i], settings.calibration[i], self.bits);
+        }
+    }
 
-                if pressed != self.pressed {
-                    self.changed = true;
-                    self.pressed = pressed;
-                } else {
-                    self.changed = false;
+    async fn get<'a>(&'a mut self, reader: &'a mut AnalogueReader<MUX_COUNT>) -> impl Iterator<Item = (usize, bool)> + 'a  {
+        reader.scan().await;
+        self.moved = false;
+        // Keys without a sensor stay released
+        for ((key, sensor), still_at) in self.keys.iter_mut().zip(&self.sensors).zip(&mut self.still_at) {
+            if let Some(sensor) = sensor {
+                key.update(reader.reading(*sensor));
+                if key.value.abs_diff(*still_at) > scale(MOVE_BAND, self.bits) {
+                    *still_at = key.value;
+                    self.moved = true;
                 }
-            },
-            KeyConfig::RappidTrigger(_) => todo!(),
+            }
         }
-    }
 
-    // TODO adjust this (does not work correctly)
-    // fn pressed_percent(&self, value: u16) -> f64 {
-    //     let a = 1.0 / libm::cbrt(value as f64);
-    //     let b = 1.0 / libm::cbrt(self.max as f64);
-    //     let c = 1.0 / libm::cbrt(self.min as f64);
-    //
-    //     (a - b) / (c - b)
-    // }
+        self.keys.iter()
+            .enumerate()
+            .filter(|k| k.1.changed)
+            .map(|k| (k.0, k.1.pressed))
+    }
 }
 
 // #[embassy_executor::task]
@@ -0,0 +1,26 @@
+//! Whether the host currently lets the pad draw power.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+static RESUMED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn set_suspended(suspended: bool) {
+	SUSPENDED.store(suspended, Ordering::Relaxed);
+	if !suspended {
+		RESUMED.signal(());
+	}
+}
+
+pub fn suspended() -> bool {
+	SUSPENDED.load(Ordering::Relaxed)
+}
+
+/// Waits until the host resumes the bus, right away if it is not suspended.
+pub async fn resumed() {
+	while suspended() {
+		RESUMED.wait().await;
+	}
+}
@@ -0,0 +1,19 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+use shared::keymap::PROFILE_COUNT;
+
+pub const DEFAULT_PROFILE: u8 = 0;
+
+static ACTIVE_PROFILE: AtomicU8 = AtomicU8::new(DEFAULT_PROFILE);
+
+pub fn active() -> u8 {
+	ACTIVE_PROFILE.load(Ordering::Relaxed)
+}
+
+/// Returns false if no such profile exists.
+pub fn select(profile: u8) -> bool {
+	if profile as usize >= PROFILE_COUNT {
+		return false;
+	}
+	ACTIVE_PROFILE.store(profile, Ordering::Relaxed);
+	true
+}
@@ -0,0 +1,333 @@
+//! Reads the hall sensors behind the multiplexers.
+//!
+//! Multiplexers are scanned one after the other, with only the one being read enabled so the others
+//! can't couple into its output. Reading a multiplexer runs without the CPU: TIM1's update event has
+//! DMA write the next address to GPIOA, its compare channel 1 fires `settle_ns` later and triggers
+//! the ADC, which converts the sensor once per oversample and has DMA store the conversions.
+
+use embassy_futures::join::join;
+use embassy_stm32::adc::{Adc, AnyAdcChannel, Resolution, RxDma, SampleTime, Temperature, VrefInt};
+use embassy_stm32::dma::{Transfer, TransferOptions};
+use embassy_stm32::gpio::{AnyPin, Level, Output, Pin, Speed};
+use embassy_stm32::pac;
+use embassy_stm32::pac::adc::vals::Exten;
+use embassy_stm32::pac::rcc::vals::Ppre;
+use embassy_stm32::peripherals::{ADC1, DMA2_CH0, DMA2_CH5, TIM1};
+use embassy_stm32::timer::{Channel, UpDma};
+use embassy_stm32::timer::low_level::{OutputCompareMode, Timer};
+use shared::config::{self, Oversampling, ScanConfig, MAX_SAMPLES};
+use shared::matrix::{Sensor, MUX_POSITIONS};
+use shared::supply::{FactoryCalibration, Supply};
+use crate::constants::{TS_CAL1, TS_CAL2, VREFINT_CAL};
+
+/// `EXTSEL` value starting regular conversions on TIM1_CC1.
+const TRIGGER_TIM1_CC1: u8 = 0;
+/// Fastest ADC clock `Adc::new` picks its prescaler for, from the datasheet.
+const MAX_ADC_CLOCK_HZ: u32 = 36_000_000;
+
+pub struct AnalogueReader<const AMOUNT: usize = 1> {
+	channels: [AnyAdcChannel<ADC1>; AMOUNT],
+	adc: Adc<'static, ADC1>,
+	timer: Timer<'static, TIM1>,
+	mux_dma: DMA2_CH5,
+	adc_dma: DMA2_CH0,
+	/// GPIOA BSRR words selecting each mux position.
+	addresses: [u32; MUX_POSITIONS],
+	/// Conversions of the multiplexer being scanned, grouped by position.
+	conversions: [u16; MUX_POSITIONS * MAX_SAMPLES],
+	readings: [[u16; MUX_POSITIONS]; AMOUNT],
+	/// CPU cycles to wait after switching the multiplexers outside of a scan.
+	settle_cycles: u32,
+	sample_time: SampleTime,
+	resolution: Resolution,
+	oversampling: Oversampling,
+	s0: Output<'static>,
+	s1: Output<'static>,
+	s2: Output<'static>,
+	s3: Output<'static>,
+	/// Active low enable of each multiplexer.
+	enables: [Output<'static>; AMOUNT],
+	vrefint: VrefInt,
+	temperature: Temperature,
+	pub supply: Supply,
+}
+
+impl<const AMOUNT: usize> AnalogueReader<AMOUNT> {
+	/// The select pins all have to be on GPIOA, a scan switches them with a single DMA write.
+	pub fn new(
+		s0: AnyPin,
+		s1: AnyPin,
+		s2: AnyPin,
+		s3: AnyPin,
+		enables: [AnyPin; AMOUNT],
+		multiplexers: [AnyAdcChannel<ADC1>; AMOUNT],
+		adc: Adc<'static, ADC1>,
+		timer: TIM1,
+		mux_dma: DMA2_CH5,
+		adc_dma: DMA2_CH0,
+	) -> Self {
+		// low speed 8MHz
+		// medium speed 50MHz
+		// maximum high-speed 100MHz
+		// very high-speed 180Mhz.
+
+		let select = [s0.pin(), s1.pin(), s2.pin(), s3.pin()];
+		defmt::assert!([s0.port(), s1.port(), s2.port(), s3.port()] == [0; 4], "mux select pins must be on GPIOA");
+		let addresses = core::array::from_fn(|address| {
+			select.iter().enumerate().fold(0, |word, (bit, pin)| {
+				let pin = *pin as u32;
+				// Set in the lower half of BSRR, reset in the upper
+				word | if address >> bit & 1 == 1 { 1 << pin } else { 1 << (pin + 16) }
+			})
+		});
+
+		// Both internal channels need ~10us to start, the first supply sample is far later than that
+		let vrefint = adc.enable_vrefint();
+		let temperature = adc.enable_temperature();
+		// Safety: system memory is read only and the calibration is present on every F411
+		let read = |address| unsafe { core::ptr::read_volatile(address as *const u16) };
+		let calibration = FactoryCalibration {
+			vrefint: read(VREFINT_CAL),
+			ts_30: read(TS_CAL1),
+			ts_110: read(TS_CAL2),
+		};
+
+		let mut reader = Self {
+			channels: multiplexers,
+			adc,
+			timer: Timer::new(timer),
+			mux_dma,
+			adc_dma,
+			addresses,
+			conversions: [0; MUX_POSITIONS * MAX_SAMPLES],
+			readings: [[0; MUX_POSITIONS]; AMOUNT],
+			settle_cycles: 0,
+			sample_time: SampleTime::CYCLES3,
+			resolution: Resolution::BITS12,
+			oversampling: Oversampling::None,
+			s0: Output::new(s0, Level::Low, Speed::Low),
+			s1: Output::new(s1, Level::Low, Speed::Low),
+			s2: Output::new(s2, Level::Low, Speed::Low),
+			s3: Output::new(s3, Level::Low, Speed::Low),
+			enables: enables.map(|en| Output::new(en, Level::High, Speed::Low)),
+			vrefint,
+			temperature,
+			supply: Supply::new(calibration),
+		};
+		reader.configure(ScanConfig::DEFAULT);
+		reader
+	}
+
+	/// Sets up the timing and resolution of the following scans.
+	pub fn configure(&mut self, scan: ScanConfig) {
+		self.sample_time = sample_time(scan.sample_time);
+		self.adc.set_sample_time(self.sample_time);
+		self.resolution = resolution(scan.resolution);
+		self.adc.set_resolution(self.resolution);
+		self.oversampling = scan.oversampling;
+		for channel in &self.channels {
+			set_channel_sample_time(channel.get_hw_channel(), self.sample_time);
+		}
+
+		let timer_hz = self.timer.get_clock_frequency().0;
+		// The compare point has to leave room for the conversions within the 16 bit period
+		let settle = (timer_hz as u64 * scan.settle_ns as u64 / 1_000_000_000).clamp(1, u16::MAX as u64 / 2) as u32;
+		let adc_cycle = timer_hz / adc_clock_hz(timer_hz);
+		// A conversion takes a cycle per bit after sampling
+		let conversion = (scan.sample_time.cycles() + scan.resolution.bits() as u32) * adc_cycle;
+		let conversion = conversion * scan.oversampling.samples() as u32;
+		// A little slack for the trigger to reach the ADC
+		let period = (settle + conversion + 2 * adc_cycle).min(u16::MAX as u32);
+		self.settle_cycles = settle;
+
+		let regs = self.timer.regs_core();
+		regs.psc().write_value(0);
+		regs.arr().write(|w| w.set_arr(period as u16 - 1));
+		// Latches the prescaler, before update DMA is enabled so it moves nothing
+		regs.egr().write(|w| w.set_ug(true));
+		self.timer.set_output_compare_mode(Channel::Ch1, OutputCompareMode::PwmMode2);
+		self.timer.set_compare_value(Channel::Ch1, settle as u16);
+		self.timer.enable_channel(Channel::Ch1, true);
+	}
+
+	/// Reads every key once, the readings are kept until the next scan.
+	///
+	/// Leaves all multiplexers disabled.
+	pub async fn scan(&mut self) {
+		for mux in 0..AMOUNT {
+			self.enable(Some(mux));
+			self.scan_mux(mux).await;
+		}
+		self.enable(None);
+	}
+
+	async fn scan_mux(&mut self, mux: usize) {
+		self.select(0);
+
+		// Each trigger converts the whole sequence, the same channel once per oversample
+		let samples = self.oversampling.samples();
+		let adc = pac::ADC1;
+		adc.cr1().modify(|w| w.set_scan(samples > 1));
+		adc.sqr1().modify(|w| w.set_l(samples as u8 - 1));
+		for i in 0..samples {
+			set_sequence(i, self.channels[mux].get_hw_channel());
+		}
+		adc.cr2().modify(|w| {
+			w.set_dma(true);
+			w.set_dds(false);
+			w.set_extsel(TRIGGER_TIM1_CC1);
+			w.set_exten(Exten::RISINGEDGE);
+		});
+
+		let mux_request = self.mux_dma.request();
+		let adc_request = self.adc_dma.request();
+		let conversions = &mut self.conversions[..MUX_POSITIONS * samples];
+		// Safety: the transfers are awaited before the buffers can be touched again
+		let (mux, convert) = unsafe {
+			(
+				// Position 0 was selected above, the update events switch to the rest
+				Transfer::new_write(
+					&mut self.mux_dma,
+					mux_request,
+					&self.addresses[1..],
+					pac::GPIOA.bsrr().as_ptr() as *mut u32,
+					TransferOptions::default(),
+				),
+				Transfer::new_read(
+					&mut self.adc_dma,
+					adc_request,
+					adc.dr().as_ptr() as *mut u16,
+					conversions,
+					TransferOptions::default(),
+				),
+			)
+		};
+
+		self.timer.reset();
+		self.timer.enable_update_dma(true);
+		self.timer.start();
+		join(mux, convert).await;
+		self.timer.stop();
+		self.timer.enable_update_dma(false);
+
+		// Back to software started conversions for `sample` and the internal channels
+		adc.cr2().modify(|w| {
+			w.set_dma(false);
+			w.set_exten(Exten::DISABLED);
+		});
+		adc.cr1().modify(|w| w.set_scan(false));
+		adc.sqr1().modify(|w| w.set_l(0));
+
+		for (reading, conversions) in self.readings[mux].iter_mut().zip(self.conversions.chunks_exact(samples)) {
+			*reading = self.oversampling.combine(conversions);
+		}
+	}
+
+	/// Latest reading of a sensor from [`AnalogueReader::scan`].
+	pub fn reading(&self, sensor: Sensor) -> u16 {
+		// Readings are relative to VDDA, which follows the USB supply
+		self.supply.normalise(self.readings[sensor.mux as usize][sensor.channel as usize])
+	}
+
+	/// Reads a single sensor right away, for when the scanner is not running yet.
+	pub fn sample(&mut self, sensor: Sensor) -> u16 {
+		self.enable(Some(sensor.mux as usize));
+		self.select(sensor.channel as usize);
+		cortex_m::asm::delay(self.settle_cycles);
+
+		let mut conversions = [0; MAX_SAMPLES];
+		let conversions = &mut conversions[..self.oversampling.samples()];
+		for conversion in conversions.iter_mut() {
+			*conversion = self.adc.blocking_read(&mut self.channels[sensor.mux as usize]);
+		}
+		let value = self.oversampling.combine(conversions);
+		self.enable(None);
+		self.supply.normalise(value)
+	}
+
+	/// Updates the supply compensation and returns the die temperature in hundredths of a degree.
+	pub fn sample_supply(&mut self) -> i16 {
+		// Too short a sample time reads both internal channels low,
+		// and the factory calibration is at 12 bits
+		self.adc.set_sample_time(SampleTime::CYCLES480);
+		self.adc.set_resolution(Resolution::BITS12);
+		let vrefint = self.adc.blocking_read(&mut self.vrefint);
+		let temperature = self.adc.blocking_read(&mut self.temperature);
+		self.adc.set_sample_time(self.sample_time);
+		self.adc.set_resolution(self.resolution);
+
+		self.supply.measure(vrefint);
+		self.supply.temperature(temperature)
+	}
+
+	/// Enables a single multiplexer, or none.
+	fn enable(&mut self, mux: Option<usize>) {
+		for (i, en) in self.enables.iter_mut().enumerate() {
+			en.set_level(Level::from(Some(i) != mux));
+		}
+	}
+
+	fn select(&mut self, address: usize) {
+		self.s0.set_level(Level::from(address & 1 == 1));
+		self.s1.set_level(Level::from((address >> 1) & 1 == 1));
+		self.s2.set_level(Level::from((address >> 2) & 1 == 1));
+		self.s3.set_level(Level::from((address >> 3) & 1 == 1));
+	}
+}
+
+/// The ADC clock following from the RCC setup, with the prescaler `Adc::new` picks for it.
+fn adc_clock_hz(timer_hz: u32) -> u32 {
+	// APB2 timers run at twice PCLK2 unless APB2 is undivided
+	let pclk2 = match pac::RCC.cfgr().read().ppre2() {
+		Ppre::DIV1 => timer_hz,
+		_ => timer_hz / 2,
+	};
+	let divider = match pclk2 / MAX_ADC_CLOCK_HZ {
+		0..=1 => 2,
+		2..=3 => 4,
+		4..=5 => 6,
+		_ => 8,
+	};
+	pclk2 / divider
+}
+
+fn sample_time(time: config::SampleTime) -> SampleTime {
+	match time {
+		config::SampleTime::Cycles3 => SampleTime::CYCLES3,
+		config::SampleTime::Cycles15 => SampleTime::CYCLES15,
+		config::SampleTime::Cycles28 => SampleTime::CYCLES28,
+		config::SampleTime::Cycles56 => SampleTime::CYCLES56,
+		config::SampleTime::Cycles84 => SampleTime::CYCLES84,
+		config::SampleTime::Cycles112 => SampleTime::CYCLES112,
+		config::SampleTime::Cycles144 => SampleTime::CYCLES144,
+		config::SampleTime::Cycles480 => SampleTime::CYCLES480,
+	}
+}
+
+fn set_channel_sample_time(channel: u8, time: SampleTime) {
+	let channel = channel as usize;
+	if channel < 10 {
+		pac::ADC1.smpr2().modify(|w| w.set_smp(channel, time));
+	} else {
+		pac::ADC1.smpr1().modify(|w| w.set_smp(channel - 10, time));
+	}
+}
+
+fn resolution(resolution: config::Resolution) -> Resolution {
+	match resolution {
+		config::Resolution::Bits12 => Resolution::BITS12,
+		config::Resolution::Bits10 => Resolution::BITS10,
+		config::Resolution::Bits8 => Resolution::BITS8,
+		config::Resolution::Bits6 => Resolution::BITS6,
+	}
+}
+
+/// Puts `channel` at position `i` of the regular sequence.
+fn set_sequence(i: usize, channel: u8) {
+	let adc = pac::ADC1;
+	match i {
+		0..6 => adc.sqr3().modify(|w| w.set_sq(i, channel)),
+		6..12 => adc.sqr2().modify(|w| w.set_sq(i - 6, channel)),
+		_ => adc.sqr1().modify(|w| w.set_sq(i - 12, channel)),
+	}
+}
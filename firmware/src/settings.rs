@@ -0,0 +1,267 @@
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use defmt::{info, warn, Debug2Format};
+use embassy_stm32::flash::{Async, Error as FlashError, Flash, FLASH_BASE};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use musli::FixedBytes;
+use shared::keymap::KEY_COUNT;
+use shared::message::IMAGE_CHUNK_LEN;
+use shared::settings::Settings;
+use shared::store::{self, Header, StoreError, HEADER_LEN, PAYLOAD_MAX_LEN};
+use crate::constants::{FLASH_OFFSET, SLOT_COUNT, SLOT_SIZE};
+use crate::make_static;
+
+/// The running settings, shared by the scanner and the USB task.
+static SETTINGS: BlockingMutex<CriticalSectionRawMutex, RefCell<Settings>> =
+	BlockingMutex::new(RefCell::new(Settings::empty()));
+
+/// Bumped on every change, so the scanner knows when to pick up new settings.
+static GENERATION: AtomicU32 = AtomicU32::new(0);
+
+/// Counted outside of [`SETTINGS`] so presses don't look like a settings change.
+static COUNTERS: [AtomicU32; KEY_COUNT] = [const { AtomicU32::new(0) }; KEY_COUNT];
+
+static STORE: Mutex<CriticalSectionRawMutex, Option<&'static mut Store>> = Mutex::new(None);
+
+struct Store {
+	flash: Flash<'static, Async>,
+	defaults: fn() -> Settings,
+	/// Slot holding the newest image and its sequence number.
+	current: Option<(u32, u32)>,
+	/// Copy of the running settings being encoded, so the lock is only held for the copy.
+	snapshot: Settings,
+	buf: FixedBytes<PAYLOAD_MAX_LEN>,
+	transfer: Transfer,
+}
+
+/// What `buf` holds between the messages of a backup or restore.
+enum Transfer {
+	None,
+	Backup,
+	Restore(Header),
+}
+
+/// Loads the newest settings stored in `USER_FLASH`, falling back to `defaults` if there are none.
+pub async fn init(flash: Flash<'static, Async>, defaults: fn() -> Settings) {
+	let mut current = None;
+	let mut settings = None;
+	for slot in 0..SLOT_COUNT {
+		match store::decode(slot_image(slot)) {
+			Ok((header, s)) => {
+				if current.map_or(true, |(_, sequence)| header.sequence > sequence) {
+					current = Some((slot, header.sequence));
+					settings = Some(s);
+				}
+			}
+			Err(StoreError::Empty) => {}
+			Err(e) => warn!("Settings slot {} is unusable: {}", slot, Debug2Format(&e)),
+		}
+	}
+
+	let settings = match settings {
+		Some(settings) => {
+			info!("Loaded settings from slot {}", current.unwrap().0);
+			settings
+		}
+		None => {
+			warn!("Using default settings, none are stored");
+			defaults()
+		}
+	};
+	apply(settings);
+
+	let store = make_static!(Store, Store {
+		flash,
+		defaults,
+		current,
+		snapshot: Settings::empty(),
+		buf: FixedBytes::new(),
+		transfer: Transfer::None,
+	});
+	*STORE.lock().await = Some(store);
+}
+
+/// Runs `f` with interrupts disabled, so it should only pick out what it needs.
+pub fn with<R>(f: impl FnOnce(&Settings) -> R) -> R {
+	SETTINGS.lock(|s| f(&s.borrow()))
+}
+
+pub fn modify<R>(f: impl FnOnce(&mut Settings) -> R) -> R {
+	let r = SETTINGS.lock(|s| f(&mut s.borrow_mut()));
+	GENERATION.fetch_add(1, Ordering::Release);
+	r
+}
+
+pub fn generation() -> u32 {
+	GENERATION.load(Ordering::Acquire)
+}
+
+pub fn count_press(key: usize) {
+	COUNTERS[key].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Replaces the running settings, counters included.
+fn apply(settings: Settings) {
+	for (counter, value) in COUNTERS.iter().zip(settings.counters) {
+		counter.store(value, Ordering::Relaxed);
+	}
+	modify(|s| *s = settings);
+}
+
+/// Writes the running settings to `USER_FLASH`.
+pub async fn save() -> Result<(), FlashError> {
+	let mut guard = STORE.lock().await;
+	let store = guard.as_mut().expect("settings::init was not called");
+	store.transfer = Transfer::None;
+
+	let header = store.encode()?;
+	store.write(header).await
+}
+
+/// Snapshots the running settings for [`read_backup`], returns the header describing the snapshot.
+pub async fn begin_backup() -> Result<Header, FlashError> {
+	let mut guard = STORE.lock().await;
+	let store = guard.as_mut().expect("settings::init was not called");
+
+	let header = store.encode()?;
+	store.transfer = Transfer::Backup;
+	Ok(header)
+}
+
+/// Copies a chunk of the backup snapshot into `data`, returns how much of it was filled.
+pub async fn read_backup(offset: usize, data: &mut [u8; IMAGE_CHUNK_LEN]) -> Option<usize> {
+	let guard = STORE.lock().await;
+	let store = guard.as_ref().expect("settings::init was not called");
+	if !matches!(store.transfer, Transfer::Backup) {
+		return None;
+	}
+
+	let chunk = store.buf.as_slice().get(offset..)?;
+	let len = chunk.len().min(IMAGE_CHUNK_LEN);
+	data[..len].copy_from_slice(&chunk[..len]);
+	Some(len)
+}
+
+pub async fn begin_restore(header: Header) -> bool {
+	let mut guard = STORE.lock().await;
+	let store = guard.as_mut().expect("settings::init was not called");
+	if header.version != store::VERSION || header.len as usize > PAYLOAD_MAX_LEN {
+		store.transfer = Transfer::None;
+		return false;
+	}
+
+	store.buf.clear();
+	store.transfer = Transfer::Restore(header);
+	true
+}
+
+/// Chunks have to arrive in order, returns false for anything else.
+pub async fn write_restore(offset: usize, data: &[u8]) -> bool {
+	let mut guard = STORE.lock().await;
+	let store = guard.as_mut().expect("settings::init was not called");
+	let Transfer::Restore(header) = store.transfer else {
+		return false;
+	};
+	if offset != store.buf.len() || offset + data.len() > header.len as usize {
+		return false;
+	}
+	store.buf.extend_from_slice(data)
+}
+
+/// Persists and applies the restored image, the running and stored settings stay as they are on error.
+pub async fn commit_restore() -> Result<(), RestoreError> {
+	let mut guard = STORE.lock().await;
+	let store = guard.as_mut().expect("settings::init was not called");
+	let Transfer::Restore(header) = core::mem::replace(&mut store.transfer, Transfer::None) else {
+		return Err(RestoreError::NoTransfer);
+	};
+
+	let settings = store::decode_payload(&header, store.buf.as_slice()).map_err(|e| {
+		warn!("Restored image is invalid: {}", Debug2Format(&e));
+		RestoreError::Invalid
+	})?;
+	store.write(header).await.map_err(RestoreError::Flash)?;
+	apply(settings);
+	Ok(())
+}
+
+/// Wipes `USER_FLASH` and goes back to the compiled in defaults.
+pub async fn factory_reset() -> Result<(), FlashError> {
+	let mut guard = STORE.lock().await;
+	let store = guard.as_mut().expect("settings::init was not called");
+	store.transfer = Transfer::None;
+
+	// Even if erasing fails halfway, the running settings should not keep the broken keymap
+	apply((store.defaults)());
+	store.current = None;
+	store.flash.erase(FLASH_OFFSET, FLASH_OFFSET + SLOT_COUNT * SLOT_SIZE).await?;
+
+	info!("Factory reset done");
+	Ok(())
+}
+
+pub enum RestoreError {
+	NoTransfer,
+	Invalid,
+	Flash(FlashError),
+}
+
+impl Store {
+	/// Encodes the running settings with up to date counters into `buf`.
+	fn encode(&mut self) -> Result<Header, FlashError> {
+		SETTINGS.lock(|s| self.snapshot.clone_from(&s.borrow()));
+		for (value, counter) in self.snapshot.counters.iter_mut().zip(&COUNTERS) {
+			*value = counter.load(Ordering::Relaxed);
+		}
+		store::encode(&self.snapshot, &mut self.buf).map_err(|e| {
+			warn!("Encoding settings failed: {}", Debug2Format(&e));
+			FlashError::Size
+		})
+	}
+
+	/// Writes `buf` to the slot not holding the current image.
+	///
+	/// The header goes last, so an interrupted write leaves the previous image as the newest valid one.
+	async fn write(&mut self, mut header: Header) -> Result<(), FlashError> {
+		let (slot, sequence) = match self.current {
+			Some((slot, sequence)) => ((slot + 1) % SLOT_COUNT, sequence.wrapping_add(1)),
+			None => (0, 0),
+		};
+		header.sequence = sequence;
+
+		let start = FLASH_OFFSET + slot * SLOT_SIZE;
+		self.flash.erase(start, start + SLOT_SIZE).await?;
+		write_padded(&mut self.flash, start + HEADER_LEN as u32, self.buf.as_slice()).await?;
+		self.flash.write(start, &header.to_bytes()).await?;
+
+		self.current = Some((slot, sequence));
+		info!("Saved {} bytes of settings to slot {}", header.image_len(), slot);
+		Ok(())
+	}
+}
+
+fn slot_image(slot: u32) -> &'static [u8] {
+	// SAFETY: USER_FLASH is memory mapped, only read before the store exists
+	unsafe {
+		core::slice::from_raw_parts(
+			(FLASH_BASE as u32 + FLASH_OFFSET + slot * SLOT_SIZE) as *const u8,
+			SLOT_SIZE as usize,
+		)
+	}
+}
+
+/// Flash is programmed in words, fills up the last one with the erased value.
+async fn write_padded(flash: &mut Flash<'static, Async>, offset: u32, data: &[u8]) -> Result<(), FlashError> {
+	let full = data.len() & !3;
+	flash.write(offset, &data[..full]).await?;
+
+	let rest = &data[full..];
+	if !rest.is_empty() {
+		let mut word = [0xFF; 4];
+		word[..rest.len()].copy_from_slice(rest);
+		flash.write(offset + full as u32, &word).await?;
+	}
+	Ok(())
+}
@@ -0,0 +1,16 @@
+//! Key presses requested by the host, handed to the scanner to resolve like real ones.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+
+static PENDING: Channel<CriticalSectionRawMutex, (usize, bool), 4> = Channel::new();
+
+/// `false` if the scanner has not caught up with the previous requests yet.
+pub fn press(key: usize, pressed: bool) -> bool {
+	PENDING.try_send((key, pressed)).is_ok()
+}
+
+/// The next requested key change, for the scanner to apply.
+pub fn next() -> Option<(usize, bool)> {
+	PENDING.try_receive().ok()
+}
@@ -0,0 +1,34 @@
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use shared::key::DriftReport;
+use shared::keymap::KEY_COUNT;
+use shared::stats::ScanStats;
+
+/// Measurements the scanner publishes for the host to query.
+pub struct Telemetry {
+	pub drift: [DriftReport; KEY_COUNT],
+	pub vdda_mv: u16,
+	/// In hundredths of a degree.
+	pub temperature: i16,
+	/// Full scans of the matrix since boot, wrapping.
+	pub scans: u32,
+	/// Kept by the scanner while it runs at a fixed rate.
+	pub stats: ScanStats,
+}
+
+static TELEMETRY: Mutex<CriticalSectionRawMutex, RefCell<Telemetry>> = Mutex::new(RefCell::new(Telemetry {
+	drift: [DriftReport { baseline: 0, offset: 0, limited: false }; KEY_COUNT],
+	vdda_mv: 0,
+	temperature: 0,
+	scans: 0,
+	stats: ScanStats::new(),
+}));
+
+pub fn update(f: impl FnOnce(&mut Telemetry)) {
+	TELEMETRY.lock(|t| f(&mut t.borrow_mut()))
+}
+
+pub fn with<R>(f: impl FnOnce(&Telemetry) -> R) -> R {
+	TELEMETRY.lock(|t| f(&t.borrow()))
+}
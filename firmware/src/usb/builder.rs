@@ -5,8 +5,11 @@ use crate::{make_static};
 use crate::usb::config::get_usb_config;
 use crate::usb::Irqs;
 
+/// Every interface's descriptors go into the configuration descriptor, MIDI's jacks take the most.
+const CONFIG_DESCRIPTOR_LEN: usize = 512;
+
 struct Buffers {
-	config_descriptor : [u8; 256],
+	config_descriptor : [u8; CONFIG_DESCRIPTOR_LEN],
 	bos_descriptor : [u8; 256],
 	msos_descriptor : [u8; 256],
 	control_buf : [u8; 64],
@@ -24,7 +27,7 @@ pub fn get_builder(usb: USB_OTG_FS, pa12: PA12, pa11: PA11) -> Builder<'static,
 	let config = get_usb_config();
 
 	let bufs = make_static!(Buffers, Buffers {
-config_descriptor: [0; 256],bos_descriptor: [0; 256],msos_descriptor: [0; 256],control_buf: [0; 64],});
+config_descriptor: [0; CONFIG_DESCRIPTOR_LEN],bos_descriptor: [0; 256],msos_descriptor: [0; 256],control_buf: [0; 64],});
 
 
 	Builder::new(
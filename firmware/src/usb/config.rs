@@ -1,14 +1,15 @@
-use embassy_usb::class::hid;
 use embassy_usb::Config;
-use usbd_hid::descriptor::{KeyboardReport, SerializedDescriptor};
+use shared::config::UsbConfig;
 use shared::{PRODUCT_ID, VENDOR_ID};
 use embassy_usb::class::web_usb::{Config as WebUsbConfig};
+use crate::usb::keyboard::{KeyboardConfig, MAX_REPORT_LEN};
 
 pub fn get_usb_config() -> Config<'static> {
 	let mut config = Config::new(VENDOR_ID, PRODUCT_ID);
 	config.manufacturer = Some("magneto_pad_manufacturer");
 	config.product = Some("magneto_pad_product");
-	config.serial_number = Some("magneto_pad_serial_nr");
+	// Unique per chip, so hosts can tell several pads apart
+	config.serial_number = Some(embassy_stm32::uid::uid_hex());
 	config.max_power = 100;
 	config.max_packet_size_0 = 64;
 	config.composite_with_iads = true;
@@ -18,17 +19,19 @@ pub fn get_usb_config() -> Config<'static> {
 	config
 }
 
-pub fn get_device_configs() -> (hid::Config<'static>, &'static WebUsbConfig<'static>) {
+pub fn get_device_configs(usb: &UsbConfig) -> (KeyboardConfig, &'static WebUsbConfig<'static>) {
 	static WEB_USB_CONFIG: WebUsbConfig = WebUsbConfig {
 		max_packet_size: 64,
 		landing_url: None,
 		vendor_code: 1,
 	};
-	let config = hid::Config {
-		report_descriptor: KeyboardReport::desc(),
+	let config = KeyboardConfig {
+		rollover: usb.rollover,
 		request_handler: None,
-		poll_ms: 60,
-		max_packet_size: 64,
+		// Full speed interrupt endpoints are polled every 1 to 255 frames
+		poll_ms: usb.poll_ms.max(1),
+		// Shared by all reports, the boot report still goes out in a single 8 byte transfer
+		max_packet_size: MAX_REPORT_LEN as u16,
 	};
 	(config, &WEB_USB_CONFIG)
-}
\ No newline at end of file
+}
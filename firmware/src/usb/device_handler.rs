@@ -1,7 +1,7 @@
 use core::sync::atomic::{AtomicBool, Ordering};
 use defmt::info;
 use embassy_usb::Handler;
-use crate::make_static;
+use crate::{make_static, power};
 
 pub struct DeviceHandler {
 	configured: AtomicBool,
@@ -43,4 +43,13 @@ impl Handler for DeviceHandler {
 			info!("Device is no longer configured, the Vbus current limit is 100mA.");
 		}
 	}
+
+	fn suspended(&mut self, suspended: bool) {
+		if suspended {
+			info!("Host suspended the bus, stopping the scanner");
+		} else {
+			info!("Host resumed the bus");
+		}
+		power::set_suspended(suspended);
+	}
 }
\ No newline at end of file
@@ -0,0 +1,309 @@
+//! HID keyboard interface, sending every key or the boot report depending on the setting and the host.
+//!
+//! The media keys, the mouse and the gamepad share its endpoint under their own report IDs, the OTG_FS peripheral
+//! only has three IN endpoints besides the control one. Hosts in the boot protocol only get the keys.
+//!
+//! embassy-usb's `HidReaderWriter` rejects the boot protocol, so this answers the HID requests itself.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use defmt::info;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Duration;
+use embassy_usb::class::hid::{ReportId, RequestHandler};
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::driver::{Driver, EndpointError, EndpointIn};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Builder, Handler};
+use heapless::Vec;
+use shared::config::Rollover;
+use shared::report::{
+	report_descriptor, ConsumerReport, GamepadReport, MouseReport, NkroReport, ReportFormat, CONSUMER_REPORT_ID,
+	GAMEPAD_REPORT_ID, KEYBOARD_REPORT_ID, MOUSE_REPORT_ID, NKRO_REPORT_LEN, REPORT_DESCRIPTOR_MAX_LEN,
+};
+use crate::make_static;
+
+const USB_CLASS_HID: u8 = 0x03;
+/// Marks the interface as usable without parsing its report descriptor, which BIOS setups rely on.
+const HID_SUBCLASS_BOOT: u8 = 0x01;
+const HID_PROTOCOL_KEYBOARD: u8 = 0x01;
+
+const HID_DESC_DESCTYPE_HID: u8 = 0x21;
+const HID_DESC_DESCTYPE_HID_REPORT: u8 = 0x22;
+
+const HID_REQ_GET_REPORT: u8 = 0x01;
+const HID_REQ_GET_IDLE: u8 = 0x02;
+const HID_REQ_GET_PROTOCOL: u8 = 0x03;
+const HID_REQ_SET_REPORT: u8 = 0x09;
+const HID_REQ_SET_IDLE: u8 = 0x0a;
+const HID_REQ_SET_PROTOCOL: u8 = 0x0b;
+
+/// Values of GET_PROTOCOL and SET_PROTOCOL.
+const PROTOCOL_BOOT: u16 = 0;
+const PROTOCOL_REPORT: u16 = 1;
+
+/// Longest report the endpoint carries, its ID included.
+pub const MAX_REPORT_LEN: usize = 64;
+/// Report IDs up to this one are kept for GET_REPORT.
+const LAST_REPORT_ID: u8 = GAMEPAD_REPORT_ID;
+
+/// What the host last got of each report, indexed by report ID with the boot report at 0.
+static SENT: Mutex<CriticalSectionRawMutex, RefCell<[Vec<u8, MAX_REPORT_LEN>; LAST_REPORT_ID as usize + 1]>> =
+	Mutex::new(RefCell::new([const { Vec::new() }; LAST_REPORT_ID as usize + 1]));
+
+/// Keyboards repeat their report every 500ms until the host sets another rate.
+const DEFAULT_IDLE_MS: u32 = 500;
+/// `u32::MAX` to only send reports on change.
+static IDLE_MS: AtomicU32 = AtomicU32::new(DEFAULT_IDLE_MS);
+static IDLE_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Copies the report last sent under `id` into `buf`, for GET_REPORT.
+pub fn sent_report(id: u8, buf: &mut [u8]) -> Option<usize> {
+	SENT.lock(|sent| {
+		let sent = sent.borrow();
+		let sent = sent.get(id as usize)?;
+		let len = sent.len().min(buf.len());
+		buf[..len].copy_from_slice(&sent[..len]);
+		Some(len)
+	})
+}
+
+pub fn set_idle_ms(ms: u32) {
+	IDLE_MS.store(ms, Ordering::Relaxed);
+	IDLE_CHANGED.signal(());
+}
+
+pub fn idle_ms() -> u32 {
+	IDLE_MS.load(Ordering::Relaxed)
+}
+
+/// How long to wait before repeating an unchanged report, `None` to never repeat it.
+pub fn idle() -> Option<Duration> {
+	match idle_ms() {
+		u32::MAX => None,
+		ms => Some(Duration::from_millis(ms as u64)),
+	}
+}
+
+pub async fn idle_changed() {
+	IDLE_CHANGED.wait().await
+}
+
+pub struct KeyboardConfig {
+	pub rollover: Rollover,
+	pub poll_ms: u8,
+	pub max_packet_size: u16,
+	pub request_handler: Option<&'static mut dyn RequestHandler>,
+}
+
+pub struct Keyboard<'d, D: Driver<'d>> {
+	ep_in: D::EndpointIn,
+	rollover: Rollover,
+	/// Whether the host switched to the boot protocol.
+	boot: &'static AtomicBool,
+}
+
+impl<'d, D: Driver<'d>> Keyboard<'d, D> {
+	pub fn new(builder: &mut Builder<'d, D>, config: KeyboardConfig) -> Self {
+		let buf = make_static!([u8; REPORT_DESCRIPTOR_MAX_LEN], [0; REPORT_DESCRIPTOR_MAX_LEN]);
+		let report_descriptor = report_descriptor(config.rollover, buf);
+		let len = report_descriptor.len() as u16;
+		let hid_descriptor = [
+			9,
+			HID_DESC_DESCTYPE_HID,
+			// HID 1.11
+			0x11, 0x01,
+			// Not localized
+			0,
+			1,
+			HID_DESC_DESCTYPE_HID_REPORT,
+			len as u8, (len >> 8) as u8,
+		];
+
+		let mut func = builder.function(USB_CLASS_HID, HID_SUBCLASS_BOOT, HID_PROTOCOL_KEYBOARD);
+		let mut iface = func.interface();
+		let if_num = iface.interface_number();
+		let mut alt = iface.alt_setting(USB_CLASS_HID, HID_SUBCLASS_BOOT, HID_PROTOCOL_KEYBOARD, None);
+		alt.descriptor(HID_DESC_DESCTYPE_HID, &hid_descriptor[2..]);
+		let ep_in = alt.endpoint_interrupt_in(config.max_packet_size, config.poll_ms);
+		drop(func);
+
+		let boot = make_static!(AtomicBool, AtomicBool::new(false));
+		let control = make_static!(Control, Control {
+			if_num,
+			report_descriptor,
+			hid_descriptor,
+			request_handler: config.request_handler,
+			boot,
+		});
+		builder.handler(control);
+
+		// GET_REPORT before the first change gets nothing pressed
+		let mut buf = [0; NKRO_REPORT_LEN];
+		remember(0, NkroReport::new().encode(ReportFormat::Boot, &mut buf));
+		remember(KEYBOARD_REPORT_ID, NkroReport::new().encode(ReportFormat::negotiated(config.rollover, false), &mut buf));
+		remember(CONSUMER_REPORT_ID, ConsumerReport::default().as_bytes());
+		remember(MOUSE_REPORT_ID, &MouseReport::default().to_bytes());
+		remember(GAMEPAD_REPORT_ID, &GamepadReport::default().to_bytes());
+
+		Self { ep_in, rollover: config.rollover, boot }
+	}
+
+	/// Waits until the host configured the device.
+	pub async fn ready(&mut self) {
+		self.ep_in.wait_enabled().await
+	}
+
+	/// Sends the keys in the format the host currently expects.
+	pub async fn write(&mut self, report: &NkroReport) -> Result<(), EndpointError> {
+		let boot = self.boot.load(Ordering::Relaxed);
+		let format = ReportFormat::negotiated(self.rollover, boot);
+		let mut buf = [0; NKRO_REPORT_LEN];
+		let bytes = report.encode(format, &mut buf);
+		if boot {
+			self.ep_in.write(bytes).await?;
+			remember(0, bytes);
+			Ok(())
+		} else {
+			self.write_report(KEYBOARD_REPORT_ID, bytes).await
+		}
+	}
+
+	/// Sends one of the other reports, unless the host only takes the keys.
+	pub async fn write_report(&mut self, id: u8, report: &[u8]) -> Result<(), EndpointError> {
+		if id != KEYBOARD_REPORT_ID && self.boot.load(Ordering::Relaxed) {
+			return Ok(());
+		}
+		let mut buf = Vec::<u8, MAX_REPORT_LEN>::new();
+		buf.push(id).unwrap();
+		buf.extend_from_slice(report).unwrap();
+		self.ep_in.write(&buf).await?;
+		remember(id, report);
+		Ok(())
+	}
+}
+
+/// Keeps `report` for GET_REPORT, with the ID in front like on the endpoint.
+fn remember(id: u8, report: &[u8]) {
+	SENT.lock(|sent| {
+		let mut sent = sent.borrow_mut();
+		let Some(sent) = sent.get_mut(id as usize) else {
+			return;
+		};
+		sent.clear();
+		if id != 0 {
+			sent.push(id).unwrap();
+		}
+		sent.extend_from_slice(report).unwrap();
+	});
+}
+
+struct Control {
+	if_num: InterfaceNumber,
+	report_descriptor: &'static [u8],
+	hid_descriptor: [u8; 9],
+	request_handler: Option<&'static mut dyn RequestHandler>,
+	boot: &'static AtomicBool,
+}
+
+impl Handler for Control {
+	/// Hosts expect the report protocol and the default idle rate after a reset, like after plugging in.
+	fn reset(&mut self) {
+		self.boot.store(false, Ordering::Relaxed);
+		IDLE_MS.store(DEFAULT_IDLE_MS, Ordering::Relaxed);
+	}
+
+	fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+		if (req.request_type, req.recipient, req.index) != (RequestType::Class, Recipient::Interface, self.if_num.0 as u16) {
+			return None;
+		}
+
+		match req.request {
+			HID_REQ_SET_IDLE => {
+				if let Some(handler) = self.request_handler.as_mut() {
+					let id = req.value as u8;
+					let id = (id != 0).then_some(ReportId::In(id));
+					// In steps of 4ms, 0 for reports only on change
+					let dur = match req.value >> 8 {
+						0 => u32::MAX,
+						dur => dur as u32 * 4,
+					};
+					handler.set_idle_ms(id, dur);
+				}
+				Some(OutResponse::Accepted)
+			}
+			HID_REQ_SET_REPORT => match (report_id(req.value), self.request_handler.as_mut()) {
+				(Some(id), Some(handler)) => Some(handler.set_report(id, data)),
+				_ => Some(OutResponse::Rejected),
+			},
+			HID_REQ_SET_PROTOCOL => {
+				let boot = match req.value {
+					PROTOCOL_BOOT => true,
+					PROTOCOL_REPORT => false,
+					_ => return Some(OutResponse::Rejected),
+				};
+				info!("Host switched to the {} protocol", if boot { "boot" } else { "report" });
+				self.boot.store(boot, Ordering::Relaxed);
+				Some(OutResponse::Accepted)
+			}
+			_ => Some(OutResponse::Rejected),
+		}
+	}
+
+	fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+		if (req.recipient, req.index) != (Recipient::Interface, self.if_num.0 as u16) {
+			return None;
+		}
+
+		match (req.request_type, req.request) {
+			(RequestType::Standard, Request::GET_DESCRIPTOR) => match (req.value >> 8) as u8 {
+				HID_DESC_DESCTYPE_HID_REPORT => Some(InResponse::Accepted(self.report_descriptor)),
+				HID_DESC_DESCTYPE_HID => Some(InResponse::Accepted(&self.hid_descriptor)),
+				_ => Some(InResponse::Rejected),
+			},
+			(RequestType::Class, HID_REQ_GET_REPORT) => {
+				let len = report_id(req.value)
+					.zip(self.request_handler.as_mut())
+					.and_then(|(id, handler)| handler.get_report(id, buf));
+				match len {
+					Some(len) => Some(InResponse::Accepted(&buf[..len])),
+					None => Some(InResponse::Rejected),
+				}
+			}
+			(RequestType::Class, HID_REQ_GET_IDLE) => {
+				let id = req.value as u8;
+				let id = (id != 0).then_some(ReportId::In(id));
+				match self.request_handler.as_mut().and_then(|handler| handler.get_idle_ms(id)) {
+					Some(dur) => {
+						buf[0] = match dur {
+							u32::MAX => 0,
+							dur => (dur / 4).clamp(1, u8::MAX as u32) as u8,
+						};
+						Some(InResponse::Accepted(&buf[..1]))
+					}
+					None => Some(InResponse::Rejected),
+				}
+			}
+			(RequestType::Class, HID_REQ_GET_PROTOCOL) => {
+				let protocol = if self.boot.load(Ordering::Relaxed) { PROTOCOL_BOOT } else { PROTOCOL_REPORT };
+				buf[0] = protocol as u8;
+				Some(InResponse::Accepted(&buf[..1]))
+			}
+			(RequestType::Class, _) => Some(InResponse::Rejected),
+			_ => None,
+		}
+	}
+}
+
+/// Report type and id from the value of GET_REPORT and SET_REPORT.
+fn report_id(value: u16) -> Option<ReportId> {
+	let id = value as u8;
+	match value >> 8 {
+		1 => Some(ReportId::In(id)),
+		2 => Some(ReportId::Out(id)),
+		3 => Some(ReportId::Feature(id)),
+		_ => None,
+	}
+}
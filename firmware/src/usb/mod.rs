@@ -2,51 +2,87 @@ mod config;
 mod builder;
 mod web_usb;
 mod device_handler;
+mod protocol;
+mod keyboard;
 
+use core::cell::{Cell, RefCell};
+use core::future::pending;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use defmt::*;
-use embassy_futures::join::{join, join3};
+use embassy_futures::join::{join, join4};
+use embassy_futures::select::{select, select4, Either, Either4};
 use embassy_stm32::{bind_interrupts, peripherals, usb};
 use embassy_stm32::peripherals::{PA11, PA12, USB_OTG_FS};
-use embassy_usb::class::hid::{HidReaderWriter, ReportId, RequestHandler, State};
+use embassy_usb::class::hid::{ReportId, RequestHandler};
+use embassy_usb::class::midi::MidiClass;
 use embassy_usb::control::OutResponse;
 use embassy_usb::{Builder};
 use embassy_usb::class::web_usb::{Config as WebUsbConfig, State as WebUsbState, WebUsb};
-use usbd_hid::descriptor::{KeyboardReport};
-use embassy_sync::blocking_mutex::raw::NoopRawMutex;
-use embassy_sync::channel::Receiver;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::pubsub::{PubSubChannel, WaitResult};
-use embassy_usb::driver::{Driver, Endpoint, EndpointIn, EndpointOut};
-use keyberon::key_code::KbHidReport;
+use embassy_usb::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use embassy_time::{Instant, Timer};
 use {defmt_rtt as _, panic_probe as _};
-use shared::message::{ Message};
-use crate::{make_static};
+use shared::message::{Message, Stream};
+use shared::midi::MidiEvent;
+use shared::report::{
+	AnalogReport, ConsumerReport, GamepadReport, Leds, MouseReport, NkroReport, ANALOG_PAGES, ANALOG_PAGE_LEN,
+	ANALOG_REPORT_ID, CONSUMER_REPORT_ID, GAMEPAD_REPORT_ID, KEYBOARD_REPORT_ID, MOUSE_REPORT_ID,
+};
+use crate::{indicators, make_static, settings};
+use crate::handoff::Receiver;
+use crate::{
+	ANALOG_QUEUE_LEN, CONSUMER_QUEUE_LEN, EVENT_QUEUE_LEN, GAMEPAD_QUEUE_LEN, MIDI_QUEUE_LEN, MOUSE_QUEUE_LEN, REPORT_QUEUE_LEN,
+};
 use crate::usb::builder::get_builder;
-use crate::usb::config::{get_device_configs};
+use crate::usb::config::get_device_configs;
 use crate::usb::device_handler::DeviceHandler;
+use crate::usb::keyboard::{self, Keyboard, KeyboardConfig};
 use crate::usb::web_usb::{UsbChannel, UsbPublisher, UsbSubscriber};
 
 bind_interrupts!(struct Irqs {
     OTG_FS => usb::InterruptHandler<peripherals::USB_OTG_FS>;
 });
 
-pub fn get_states() -> &'static mut (State<'static>, WebUsbState<'static>) {
-	let state = State::new();
-	let web_state = WebUsbState::new();
-	make_static!((State, WebUsbState), (state, web_state))
+/// What the analog pages last sent added up to, for hosts that ask before a key moves.
+static ANALOG: Mutex<CriticalSectionRawMutex, RefCell<AnalogReport>> = Mutex::new(RefCell::new(AnalogReport::new()));
+/// Page the next GET_REPORT of the analog report answers with, so a host asking repeatedly gets all of them.
+static ANALOG_PAGE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn get_states() -> &'static mut WebUsbState<'static> {
+	make_static!(WebUsbState, WebUsbState::new())
 }
 
-pub async fn setup_usb(usb: USB_OTG_FS, receiver: Receiver<'_, NoopRawMutex, KbHidReport, 10>, pa12: PA12, pa11: PA11) {
-	let (state, web_state) = get_states();
+pub async fn setup_usb(
+	usb: USB_OTG_FS,
+	mut receiver: Receiver<NkroReport, REPORT_QUEUE_LEN>,
+	mut consumer_reports: Receiver<ConsumerReport, CONSUMER_QUEUE_LEN>,
+	mut mouse_reports: Receiver<MouseReport, MOUSE_QUEUE_LEN>,
+	mut gamepad_reports: Receiver<GamepadReport, GAMEPAD_QUEUE_LEN>,
+	mut analog_reports: Receiver<AnalogReport, ANALOG_QUEUE_LEN>,
+	mut midi_events: Receiver<MidiEvent, MIDI_QUEUE_LEN>,
+	mut host_events: Receiver<Message, EVENT_QUEUE_LEN>,
+	pa12: PA12,
+	pa11: PA11,
+) {
+	let web_state = get_states();
 
 	let device_handler = DeviceHandler::new();
-	let mut request_handler = MyRequestHandler {};
+	let request_handler = make_static!(MyRequestHandler, MyRequestHandler {});
 
 	let mut builder = get_builder(usb, pa12, pa11);
 	builder.handler(device_handler);
 
-	let (config, web_usb_config) = get_device_configs();
+	// Descriptors are fixed once built, see `reenumerate`
+	let enumerated = settings::with(|s| s.usb);
+	let (config, web_usb_config) = get_device_configs(&enumerated);
 
-	let hid = HidReaderWriter::<_, 1, 8>::new(&mut builder, state, config);
+	// OTG_FS has three endpoints each way besides the control one: all HID reports share one IN,
+	// MIDI and the vendor interface take one each way, which leaves one OUT
+	let mut keyboard = Keyboard::new(&mut builder, KeyboardConfig { request_handler: Some(request_handler), ..config });
+	// One jack each way, what the host plays to the pad is ignored
+	let mut midi = MidiClass::new(&mut builder, 1, 1, 64);
 	WebUsb::configure(&mut builder, web_state, &web_usb_config);
 
 	let mut endpoints = WebEndpoints::new(&mut builder, &web_usb_config);
@@ -57,31 +93,81 @@ pub async fn setup_usb(usb: USB_OTG_FS, receiver: Receiver<'_, NoopRawMutex, KbH
 	// Run the USB device.
 	let usb_fut = usb.run();
 
-	let (reader, mut writer) = hid.split();
-
+	// Writes fail while the host is away or resets the device, they pick up once it is configured again
 	let hid_writer_fut = async {
-		info!("wait ready");
-		writer.ready().await;
-		info!("ready");
-
+		let mut report = NkroReport::new();
 		loop {
-			let msg = receiver.receive().await;
-			writer.write(msg.as_bytes()).await.unwrap();
-			// writer.write_serialize(&msg).await.unwrap();
+			info!("wait ready");
+			keyboard.ready().await;
+			info!("ready");
+
+			let mut repeat_at = keyboard::idle().map(|idle| Instant::now() + idle);
+			loop {
+				let repeat = async {
+					match repeat_at {
+						Some(at) => Timer::at(at).await,
+						None => pending().await,
+					}
+				};
+				let others = select4(
+					consumer_reports.receive(),
+					mouse_reports.receive(),
+					gamepad_reports.receive(),
+					analog_reports.receive(),
+				);
+				// Polled in order, so keys go out before the other reports
+				let (sent, keys) = match select4(receiver.receive(), repeat, others, keyboard::idle_changed()).await {
+					Either4::First(next) => {
+						report = next;
+						(keyboard.write(&report).await, true)
+					}
+					// Hosts that set an idle rate expect the unchanged report that often
+					Either4::Second(()) => (keyboard.write(&report).await, true),
+					Either4::Third(Either4::First(consumer)) => {
+						(keyboard.write_report(CONSUMER_REPORT_ID, consumer.as_bytes()).await, false)
+					}
+					Either4::Third(Either4::Second(mouse)) => (keyboard.write_report(MOUSE_REPORT_ID, &mouse.to_bytes()).await, false),
+					Either4::Third(Either4::Third(gamepad)) => {
+						(keyboard.write_report(GAMEPAD_REPORT_ID, &gamepad.to_bytes()).await, false)
+					}
+					Either4::Third(Either4::Fourth(analog)) => (write_analog(&mut keyboard, analog).await, false),
+					// The new rate counts from now
+					Either4::Fourth(()) => (Ok(()), true),
+				};
+				if sent.is_err() {
+					break;
+				}
+				if keys {
+					repeat_at = keyboard::idle().map(|idle| Instant::now() + idle);
+				}
+			}
 		}
 	};
 
-	let out_fut = async {
-		reader.run(false, &mut request_handler).await;
+	let midi_fut = async {
+		loop {
+			midi.wait_connection().await;
+			info!("Connected midi");
+			loop {
+				let event = midi_events.receive().await;
+				if midi.write_packet(&event.to_packet()).await.is_err() {
+					break;
+				}
+			}
+		}
 	};
 
 	let channel: UsbChannel = PubSubChannel::new();
+	// Subscriptions end with the connection, see `Stream`
+	let key_events = Cell::new(false);
 
 	let webusb = async {
 		loop {
 			endpoints.wait_connected().await;
 			info!("Connected webusb");
 			endpoints.run_webusb(channel.publisher().unwrap(), channel.subscriber().unwrap()).await;
+			info!("Disconnected webusb");
+			key_events.set(false);
 		}
 	};
 
@@ -89,45 +175,113 @@ pub async fn setup_usb(usb: USB_OTG_FS, receiver: Receiver<'_, NoopRawMutex, KbH
 		let mut sub = channel.subscriber().unwrap();
 		let publisher = channel.publisher().unwrap();
 		loop {
-			match sub.next_message().await {
-				WaitResult::Lagged(x) => {error!("Channel lagged for {} messages", x)}
-				WaitResult::Message((should_write, msg)) => {
+			match select(sub.next_message(), host_events.receive()).await {
+				Either::First(WaitResult::Lagged(x)) => {error!("Channel lagged for {} messages", x)}
+				Either::First(WaitResult::Message((should_write, msg))) => {
 					if !should_write {
 						match msg {
-							Message::Ping => {
-								publisher.publish((true, Message::Pong)).await;
+							Message::Subscribe(Stream::KeyEvents) => key_events.set(true),
+							Message::Unsubscribe(Stream::KeyEvents) => key_events.set(false),
+							msg => {
+								let persists = matches!(msg, Message::SaveSettings | Message::CommitRestore | Message::FactoryReset);
+								if let Some(reply) = protocol::handle(msg).await {
+									let saved = reply == Message::Ack;
+									publisher.publish((true, reply)).await;
+									if persists && saved && settings::with(|s| s.usb) != enumerated {
+										reenumerate().await;
+									}
+								}
 							}
-							_ => {}
 						}
 					}
 				}
+				Either::Second(event) => {
+					// A host that stops reading must not hold up the answers to its requests
+					if key_events.get() && publisher.try_publish((true, event)).is_err() {
+						warn!("Host is behind, dropped a key event");
+					}
+				}
 			}
 		}
 	};
 
-	join3(join(usb_fut, webusb), join(hid_writer_fut, out_fut), ping_pong).await;
+	let benchmark = async {
+		let publisher = channel.publisher().unwrap();
+		loop {
+			let reply = protocol::benchmark().await;
+			publisher.publish((true, reply)).await;
+		}
+	};
+
+	join4(join(usb_fut, webusb), hid_writer_fut, midi_fut, join(ping_pong, benchmark)).await;
+}
+
+/// Sends the pages of `report` that changed since the last one.
+async fn write_analog<'d, D: Driver<'d>>(keyboard: &mut Keyboard<'d, D>, report: AnalogReport) -> Result<(), EndpointError> {
+	let previous = ANALOG.lock(|sent| sent.borrow().clone());
+	for page in report.changed_pages(&previous) {
+		keyboard.write_report(ANALOG_REPORT_ID, &report.to_page(page)).await?;
+	}
+	ANALOG.lock(|sent| *sent.borrow_mut() = report);
+	Ok(())
+}
+
+/// Drops off the bus and comes back with the stored settings' descriptors.
+///
+/// embassy-usb can't rebuild descriptors of a running device, so this resets the whole chip.
+async fn reenumerate() -> ! {
+	info!("USB settings changed, enumerating again");
+	// Give the answer time to reach the host
+	Timer::after_millis(100).await;
+	cortex_m::peripheral::SCB::sys_reset()
 }
 
 struct MyRequestHandler {}
 
+/// Requests to the HID interface, for its input reports and the keyboard's LED output report.
 impl RequestHandler for MyRequestHandler {
-	fn get_report(&mut self, id: ReportId, _buf: &mut [u8]) -> Option<usize> {
-		info!("Get report for {:?}", id);
-		None
+	fn get_report(&mut self, id: ReportId, buf: &mut [u8]) -> Option<usize> {
+		match id {
+			ReportId::In(ANALOG_REPORT_ID) => {
+				let page = ANALOG_PAGE.fetch_add(1, Ordering::Relaxed) % ANALOG_PAGES;
+				let bytes = ANALOG.lock(|sent| sent.borrow().to_page(page));
+				let report = buf.get_mut(..1 + ANALOG_PAGE_LEN)?;
+				report[0] = ANALOG_REPORT_ID;
+				report[1..].copy_from_slice(&bytes);
+				Some(report.len())
+			}
+			ReportId::In(id) => keyboard::sent_report(id, buf),
+			_ => {
+				warn!("Host asked for unknown report {:?}", id);
+				None
+			}
+		}
 	}
 
 	fn set_report(&mut self, id: ReportId, data: &[u8]) -> OutResponse {
-		info!("Set report for {:?}: {=[u8]}", id, data);
-		OutResponse::Accepted
+		// The report ID leads the data outside of the boot protocol
+		match (id, data) {
+			(ReportId::Out(0), [leds, ..]) | (ReportId::Out(KEYBOARD_REPORT_ID), [KEYBOARD_REPORT_ID, leds, ..]) => {
+				indicators::set_leds(Leds(*leds));
+				OutResponse::Accepted
+			}
+			_ => {
+				warn!("Host sent unknown report {:?}: {=[u8]}", id, data);
+				OutResponse::Rejected
+			}
+		}
 	}
 
+	/// Only the keys repeat, the other reports are sent on change.
 	fn set_idle_ms(&mut self, id: Option<ReportId>, dur: u32) {
 		info!("Set idle rate for {:?} to {:?}", id, dur);
+		if matches!(id, None | Some(ReportId::In(KEYBOARD_REPORT_ID))) {
+			keyboard::set_idle_ms(dur);
+		}
 	}
 
-	fn get_idle_ms(&mut self, id: Option<ReportId>) -> Option<u32> {
-		info!("Get idle rate for {:?}", id);
-		None
+	fn get_idle_ms(&mut self, _id: Option<ReportId>) -> Option<u32> {
+		Some(keyboard::idle_ms())
 	}
 }
 
@@ -154,11 +308,14 @@ impl<'d, D: Driver<'d>> WebEndpoints<'d, D> {
 		self.read_ep.wait_enabled().await
 	}
 
+	/// Returns once the host goes away, which disables the endpoints.
 	async fn run_webusb(&mut self, publisher: UsbPublisher<'_>, mut sub: UsbSubscriber<'_>) {
 		let reader = async {
 			let mut buf = [0; 64];
 			loop {
-				let n = self.read_ep.read(&mut buf).await.unwrap();
+				let Ok(n) = self.read_ep.read(&mut buf).await else {
+					return;
+				};
 				let data = &buf[..n];
 
 				let msg = Message::deserialize(data);
@@ -172,12 +329,14 @@ impl<'d, D: Driver<'d>> WebEndpoints<'d, D> {
 					WaitResult::Message((should_write, msg)) => {
 						if should_write {
 							let ser = msg.serialize();
-							self.write_ep.write(ser.as_slice()).await.unwrap();
+							if self.write_ep.write(ser.as_slice()).await.is_err() {
+								return;
+							}
 						}
 					}
 				}
 			}
 		};
-		join(reader, writer).await;
+		select(reader, writer).await;
 	}
 }
\ No newline at end of file
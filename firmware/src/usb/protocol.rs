@@ -0,0 +1,243 @@
+use defmt::warn;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Timer;
+use shared::keymap::{KEY_COUNT, LAYER_COUNT, PROFILE_COUNT};
+use shared::message::{ErrorCode, Message, IMAGE_CHUNK_LEN};
+use shared::stats::ScanStats;
+use shared::store::Header;
+use crate::settings::RestoreError;
+use crate::{profile, settings, simulate, telemetry};
+
+/// Wakes [`benchmark`], which answers once the second is over so other requests go on meanwhile.
+static BENCHMARK: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Answers a request from the host, `None` for messages that need no answer.
+pub async fn handle(msg: Message) -> Option<Message> {
+	let reply = match msg {
+		Message::Ping => Message::Pong,
+		Message::SetProfile(id) => {
+			if !profile::select(id) {
+				warn!("Host selected unknown profile {}", id);
+			}
+			Message::ActiveProfile(profile::active())
+		}
+		Message::GetProfile => Message::ActiveProfile(profile::active()),
+		Message::GetDeviceInfo => Message::DeviceInfo {
+			keys: KEY_COUNT as u8,
+			layers: LAYER_COUNT as u8,
+			profiles: PROFILE_COUNT as u8,
+			bits: settings::with(|s| s.scan.bits()),
+			samples: settings::with(|s| s.scan.oversampling.samples() as u8),
+		},
+		Message::GetAction { profile, layer, key } => match action_index(profile, layer, key) {
+			Some((p, l, k)) => Message::Action {
+				profile,
+				layer,
+				key,
+				action: settings::with(|s| s.profiles[p].keymap[l][k]),
+			},
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::SetAction { profile, layer, key, action } => match action_index(profile, layer, key) {
+			Some((p, l, k)) if action.is_valid() => {
+				settings::modify(|s| s.profiles[p].keymap[l][k] = action);
+				Message::Ack
+			}
+			Some(_) => Message::Error(ErrorCode::InvalidAction),
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::GetKeyConfig(key) => match key_index(key) {
+			Some(k) => Message::KeyConfig { key, config: settings::with(|s| s.keys[k]) },
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::SetKeyConfig { key, config } => match key_index(key) {
+			Some(k) => {
+				settings::modify(|s| s.keys[k] = config);
+				Message::Ack
+			}
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::GetCalibration(key) => match key_index(key) {
+			Some(k) => Message::Calibration { key, calibration: settings::with(|s| s.calibration[k]) },
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::SetCalibration { key, calibration } => match key_index(key) {
+			Some(k) => {
+				settings::modify(|s| s.calibration[k] = calibration);
+				Message::Ack
+			}
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::GetDrift(key) => match key_index(key) {
+			Some(k) => {
+				let drift = telemetry::with(|t| t.drift[k]);
+				Message::Drift { key, baseline: drift.baseline, offset: drift.offset, limited: drift.limited }
+			}
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::GetSupply => telemetry::with(|t| Message::Supply { vdda_mv: t.vdda_mv, temperature: t.temperature }),
+		Message::GetScanConfig => Message::ScanConfig(settings::with(|s| s.scan)),
+		Message::SetScanConfig(scan) => {
+			settings::modify(|s| s.scan = scan);
+			Message::Ack
+		}
+		Message::GetUsbConfig => Message::UsbConfig(settings::with(|s| s.usb)),
+		Message::SetUsbConfig(usb) => {
+			settings::modify(|s| s.usb = usb);
+			Message::Ack
+		}
+		Message::GetMouseConfig => Message::MouseConfig(settings::with(|s| s.mouse)),
+		Message::SetMouseConfig(mouse) => {
+			settings::modify(|s| s.mouse = mouse);
+			Message::Ack
+		}
+		Message::GetGamepadBinding { profile, key } => match profile_index(profile).zip(key_index(key)) {
+			Some((p, k)) => Message::GamepadBinding { profile, key, binding: settings::with(|s| s.profiles[p].gamepad[k]) },
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::SetGamepadBinding { profile, key, binding } => match profile_index(profile).zip(key_index(key)) {
+			Some((p, k)) => {
+				settings::modify(|s| s.profiles[p].gamepad[k] = binding);
+				Message::Ack
+			}
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::GetMidiConfig(profile) => match profile_index(profile) {
+			Some(p) => Message::MidiConfig { profile, config: settings::with(|s| s.profiles[p].midi) },
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::SetMidiConfig { profile, config } => match profile_index(profile) {
+			Some(p) if config.channel <= 15 => {
+				settings::modify(|s| s.profiles[p].midi = config);
+				Message::Ack
+			}
+			_ => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::GetMidiNote { profile, key } => match profile_index(profile).zip(key_index(key)) {
+			Some((p, k)) => Message::MidiNote { profile, key, note: settings::with(|s| s.profiles[p].notes[k]) },
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::SetMidiNote { profile, key, note } => match profile_index(profile).zip(key_index(key)) {
+			Some((p, k)) if note.map_or(true, |n| n <= 127) => {
+				settings::modify(|s| s.profiles[p].notes[k] = note);
+				Message::Ack
+			}
+			_ => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::SimulateKey { key, pressed } => match key_index(key) {
+			Some(k) if simulate::press(k, pressed) => Message::Ack,
+			Some(_) => {
+				warn!("Simulated key {} dropped, the scanner is behind", key);
+				Message::Error(ErrorCode::Busy)
+			}
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::Benchmark => {
+			BENCHMARK.signal(());
+			return None;
+		}
+		Message::GetScanStats => telemetry::with(|t| Message::ScanStats {
+			scans: t.stats.scans.try_into().unwrap_or(u32::MAX),
+			missed: t.stats.missed,
+			min: t.stats.min(),
+			avg: t.stats.avg(),
+			max: t.stats.max(),
+			avg_jitter: t.stats.avg_jitter(),
+			max_jitter: t.stats.max_jitter(),
+			max_delay: t.stats.max_delay(),
+		}),
+		Message::ResetScanStats => {
+			telemetry::update(|t| t.stats = ScanStats::new());
+			Message::Ack
+		}
+		Message::GetProfileName(profile) => match profile_index(profile) {
+			Some(p) => Message::ProfileName { profile, name: settings::with(|s| s.profiles[p].name) },
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::SetProfileName { profile, name } => match profile_index(profile) {
+			Some(p) => {
+				settings::modify(|s| s.profiles[p].name = name);
+				Message::Ack
+			}
+			None => Message::Error(ErrorCode::OutOfRange),
+		},
+		Message::SaveSettings => match settings::save().await {
+			Ok(()) => Message::Ack,
+			Err(e) => {
+				warn!("Saving settings failed: {}", e);
+				Message::Error(ErrorCode::Flash)
+			}
+		},
+		Message::BeginBackup => match settings::begin_backup().await {
+			Ok(header) => Message::ImageInfo { version: header.version, len: header.len, crc: header.crc },
+			Err(_) => Message::Error(ErrorCode::Flash),
+		},
+		Message::ReadImage(offset) => {
+			let mut data = [0; IMAGE_CHUNK_LEN];
+			match settings::read_backup(offset as usize, &mut data).await {
+				Some(len) => Message::ImageChunk { offset, len: len as u8, data },
+				None => Message::Error(ErrorCode::NoTransfer),
+			}
+		}
+		Message::BeginRestore { version, len, crc } => {
+			if settings::begin_restore(Header { version, len, crc, sequence: 0 }).await {
+				Message::Ack
+			} else {
+				Message::Error(ErrorCode::InvalidImage)
+			}
+		}
+		Message::ImageChunk { offset, len, data } => {
+			let data = &data[..(len as usize).min(IMAGE_CHUNK_LEN)];
+			if settings::write_restore(offset as usize, data).await {
+				Message::Ack
+			} else {
+				Message::Error(ErrorCode::NoTransfer)
+			}
+		}
+		Message::CommitRestore => match settings::commit_restore().await {
+			Ok(()) => Message::Ack,
+			Err(RestoreError::NoTransfer) => Message::Error(ErrorCode::NoTransfer),
+			Err(RestoreError::Invalid) => Message::Error(ErrorCode::InvalidImage),
+			Err(RestoreError::Flash(e)) => {
+				warn!("Writing restored settings failed: {}", e);
+				Message::Error(ErrorCode::Flash)
+			}
+		},
+		Message::FactoryReset => {
+			profile::select(profile::DEFAULT_PROFILE);
+			match settings::factory_reset().await {
+				Ok(()) => Message::Ack,
+				Err(e) => {
+					warn!("Factory reset failed: {}", e);
+					Message::Error(ErrorCode::Flash)
+				}
+			}
+		}
+		_ => return None,
+	};
+	Some(reply)
+}
+
+/// Counts scans for a second after each `Benchmark` request and returns the `ScanRate` answer.
+pub async fn benchmark() -> Message {
+	BENCHMARK.wait().await;
+	let start = telemetry::with(|t| t.scans);
+	Timer::after_secs(1).await;
+	Message::ScanRate { scans_per_second: telemetry::with(|t| t.scans).wrapping_sub(start) }
+}
+
+fn profile_index(profile: u8) -> Option<usize> {
+	let profile = profile as usize;
+	(profile < PROFILE_COUNT).then_some(profile)
+}
+
+fn key_index(key: u8) -> Option<usize> {
+	let key = key as usize;
+	(key < KEY_COUNT).then_some(key)
+}
+
+fn action_index(profile: u8, layer: u8, key: u8) -> Option<(usize, usize, usize)> {
+	let layer = layer as usize;
+	Some((profile_index(profile)?, (layer < LAYER_COUNT).then_some(layer)?, key_index(key)?))
+}
@@ -0,0 +1,329 @@
+//! Plugin for the Wooting Analog SDK, so games with analog keyboard support read the pad's keys.
+//!
+//! Copy the built library into the SDK's plugin directory. Keys are reported by the HID usage they
+//! have on the base layer of the active profile, keys doing anything else are left out.
+
+mod pad;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use shared::keymap::Action;
+use shared::{PRODUCT_ID, VENDOR_ID};
+use wooting_analog_plugin_dev::wooting_analog_common::*;
+use wooting_analog_plugin_dev::*;
+pub use crate::pad::{Disconnected, HidPad, HidPadSource, Pad, PadSource};
+
+/// How often reads look for pads plugged in since.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+type DeviceCallback = Box<dyn Fn(DeviceEventType, &DeviceInfo) + Send>;
+
+pub struct MagnetoPlugin<S: PadSource = HidPadSource> {
+    source: S,
+    pads: Vec<Connected<S::Pad>>,
+    callback: Option<DeviceCallback>,
+    refreshed: Option<Instant>,
+}
+
+struct Connected<P> {
+    serial: String,
+    info: DeviceInfo,
+    pad: P,
+}
+
+impl MagnetoPlugin {
+    pub fn new() -> Self {
+        Self::with_source(HidPadSource::new())
+    }
+}
+
+impl Default for MagnetoPlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: PadSource> MagnetoPlugin<S> {
+    pub fn with_source(source: S) -> Self {
+        Self {
+            source,
+            pads: Vec::new(),
+            callback: None,
+            refreshed: None,
+        }
+    }
+
+    /// Picks up pads plugged in and drops those unplugged, telling the SDK about either.
+    fn refresh(&mut self) {
+        self.refreshed = Some(Instant::now());
+        let serials = self.source.serials();
+
+        let (kept, gone) = std::mem::take(&mut self.pads).into_iter().partition(|c| serials.contains(&c.serial));
+        self.pads = kept;
+        for connected in gone {
+            self.notify(DeviceEventType::Disconnected, &connected.info);
+        }
+
+        for serial in serials {
+            if self.pads.iter().any(|c| c.serial == serial) {
+                continue;
+            }
+            let Some(pad) = self.source.open(&serial) else {
+                continue;
+            };
+            let info = DeviceInfo::new_with_id(
+                VENDOR_ID,
+                PRODUCT_ID,
+                "magneto_pad_manufacturer".to_owned(),
+                "magneto_pad".to_owned(),
+                generate_device_id(&serial, VENDOR_ID, PRODUCT_ID),
+                DeviceType::Keypad,
+            );
+            log::info!("Found pad {serial}");
+            self.notify(DeviceEventType::Connected, &info);
+            self.pads.push(Connected { serial, info, pad });
+        }
+    }
+
+    fn notify(&self, event: DeviceEventType, info: &DeviceInfo) {
+        if let Some(callback) = &self.callback {
+            callback(event, info);
+        }
+    }
+
+    /// Depth of every pressed key by HID usage, of one pad or all of them for device 0.
+    ///
+    /// A usage on several keys reads as the deepest of them.
+    fn depths(&mut self, device: DeviceID) -> Result<HashMap<u16, f32>, WootingAnalogResult> {
+        if self.callback.is_none() {
+            return Err(WootingAnalogResult::UnInitialized);
+        }
+        if self.refreshed.is_none_or(|refreshed| refreshed.elapsed() >= REFRESH_INTERVAL) {
+            self.refresh();
+        }
+
+        let mut depths = HashMap::new();
+        let mut found = false;
+        let mut gone = Vec::new();
+        for (i, connected) in self.pads.iter_mut().enumerate() {
+            if device != 0 && connected.info.device_id != device {
+                continue;
+            }
+            found = true;
+
+            let travel = match connected.pad.travel() {
+                Ok(travel) => travel,
+                Err(Disconnected) => {
+                    gone.push(i);
+                    continue;
+                }
+            };
+            let Some(keymap) = connected.pad.keymap() else {
+                continue;
+            };
+            for (key, action) in keymap.iter().enumerate() {
+                let depth = travel.depth(key).unwrap_or_default();
+                if let (Action::Key(usage), true) = (action, depth > 0.0) {
+                    let entry = depths.entry(*usage as u16).or_insert(0.0f32);
+                    *entry = entry.max(depth);
+                }
+            }
+        }
+
+        for i in gone.iter().rev() {
+            let connected = self.pads.remove(*i);
+            log::info!("Lost pad {}", connected.serial);
+            self.notify(DeviceEventType::Disconnected, &connected.info);
+        }
+        match (found, gone.is_empty()) {
+            (false, _) => Err(WootingAnalogResult::NoDevices),
+            (true, false) if device != 0 => Err(WootingAnalogResult::DeviceDisconnected),
+            _ => Ok(depths),
+        }
+    }
+}
+
+impl<S: PadSource> Plugin for MagnetoPlugin<S> {
+    fn name(&mut self) -> SDKResult<&'static str> {
+        Ok("magneto_pad").into()
+    }
+
+    fn initialise(&mut self, callback: DeviceCallback) -> SDKResult<u32> {
+        self.callback = Some(callback);
+        self.refresh();
+        Ok(self.pads.len() as u32).into()
+    }
+
+    fn is_initialised(&mut self) -> bool {
+        self.callback.is_some()
+    }
+
+    fn unload(&mut self) {
+        self.pads.clear();
+        self.callback = None;
+    }
+
+    fn device_info(&mut self) -> SDKResult<Vec<DeviceInfo>> {
+        if self.callback.is_none() {
+            return Err(WootingAnalogResult::UnInitialized).into();
+        }
+        self.refresh();
+        Ok(self.pads.iter().map(|c| c.info.clone()).collect()).into()
+    }
+
+    fn read_analog(&mut self, code: u16, device: DeviceID) -> SDKResult<f32> {
+        self.depths(device).map(|depths| depths.get(&code).copied().unwrap_or(0.0)).into()
+    }
+
+    fn read_full_buffer(&mut self, max_length: usize, device: DeviceID) -> SDKResult<HashMap<u16, f32>> {
+        self.depths(device).map(|depths| depths.into_iter().take(max_length).collect()).into()
+    }
+}
+
+declare_plugin!(MagnetoPlugin, MagnetoPlugin::new);
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use shared::key::FULL_TRAVEL;
+    use shared::keymap::{Action, KEY_COUNT};
+    use shared::report::AnalogReport;
+    use wooting_analog_plugin_dev::wooting_analog_common::*;
+    use wooting_analog_plugin_dev::Plugin;
+    use crate::{Disconnected, MagnetoPlugin, Pad, PadSource};
+
+    const A: u8 = 0x04;
+    const S: u8 = 0x16;
+    const W: u8 = 0x1A;
+
+    struct MockState {
+        serial: String,
+        plugged: bool,
+        travel: AnalogReport,
+        keymap: [Action; KEY_COUNT],
+    }
+
+    /// Pads on the desk, shared between the test and the plugin.
+    #[derive(Clone, Default)]
+    struct Desk(Arc<Mutex<Vec<MockState>>>);
+
+    impl Desk {
+        fn plug(&self, serial: &str) {
+            let mut keymap = [Action::NoOp; KEY_COUNT];
+            keymap[0] = Action::Key(A);
+            keymap[1] = Action::Key(S);
+            keymap[KEY_COUNT - 1] = Action::Layer(1);
+            self.0.lock().unwrap().push(MockState {
+                serial: serial.to_owned(),
+                plugged: true,
+                travel: AnalogReport::new(),
+                keymap,
+            });
+        }
+
+        fn with(&self, serial: &str, f: impl FnOnce(&mut MockState)) {
+            let mut pads = self.0.lock().unwrap();
+            f(pads.iter_mut().find(|p| p.serial == serial).unwrap());
+        }
+    }
+
+    struct MockPad {
+        desk: Desk,
+        serial: String,
+    }
+
+    impl Pad for MockPad {
+        fn travel(&mut self) -> Result<AnalogReport, Disconnected> {
+            let pads = self.desk.0.lock().unwrap();
+            match pads.iter().find(|p| p.serial == self.serial) {
+                Some(pad) if pad.plugged => Ok(pad.travel.clone()),
+                _ => Err(Disconnected),
+            }
+        }
+
+        fn keymap(&mut self) -> Option<[Action; KEY_COUNT]> {
+            let pads = self.desk.0.lock().unwrap();
+            pads.iter().find(|p| p.serial == self.serial).map(|p| p.keymap)
+        }
+    }
+
+    impl PadSource for Desk {
+        type Pad = MockPad;
+
+        fn serials(&mut self) -> Vec<String> {
+            self.0.lock().unwrap().iter().filter(|p| p.plugged).map(|p| p.serial.clone()).collect()
+        }
+
+        fn open(&mut self, serial: &str) -> Option<MockPad> {
+            Some(MockPad { desk: self.clone(), serial: serial.to_owned() })
+        }
+    }
+
+    type Events = Arc<Mutex<Vec<(DeviceEventType, DeviceID)>>>;
+
+    fn plugin(desk: &Desk) -> (MagnetoPlugin<Desk>, Events) {
+        let events = Events::default();
+        let mut plugin = MagnetoPlugin::with_source(desk.clone());
+        let recorded = events.clone();
+        let callback = Box::new(move |event, info: &DeviceInfo| recorded.lock().unwrap().push((event, info.device_id)));
+        plugin.initialise(callback).0.unwrap();
+        (plugin, events)
+    }
+
+    #[test]
+    fn test_reads_depth_by_keycode() {
+        let desk = Desk::default();
+        desk.plug("1");
+        desk.with("1", |pad| pad.travel.travel[1] = FULL_TRAVEL / 2);
+        let (mut plugin, _) = plugin(&desk);
+
+        assert_eq!(plugin.read_analog(S as u16, 0).0, Ok(0.5));
+        assert_eq!(plugin.read_analog(A as u16, 0).0, Ok(0.0));
+        assert_eq!(plugin.read_analog(W as u16, 0).0, Ok(0.0));
+        let buffer = plugin.read_full_buffer(16, 0).0.unwrap();
+        assert_eq!(buffer.into_iter().collect::<Vec<_>>(), [(S as u16, 0.5)]);
+    }
+
+    #[test]
+    fn test_follows_keymap() {
+        let desk = Desk::default();
+        desk.plug("1");
+        let (mut plugin, _) = plugin(&desk);
+
+        desk.with("1", |pad| {
+            pad.keymap[1] = Action::Key(W);
+            pad.travel.travel[1] = FULL_TRAVEL;
+        });
+        assert_eq!(plugin.read_analog(W as u16, 0).0, Ok(1.0));
+        assert_eq!(plugin.read_analog(S as u16, 0).0, Ok(0.0));
+    }
+
+    #[test]
+    fn test_pads_come_and_go() {
+        let desk = Desk::default();
+        desk.plug("1");
+        let (mut plugin, events) = plugin(&desk);
+        assert_eq!(plugin.device_info().0.unwrap().len(), 1);
+
+        desk.plug("2");
+        let devices = plugin.device_info().0.unwrap();
+        assert_eq!(devices.len(), 2);
+        let first = devices[0].device_id;
+        assert_ne!(first, devices[1].device_id);
+
+        desk.with("1", |pad| pad.plugged = false);
+        assert_eq!(plugin.read_analog(S as u16, first).0, Err(WootingAnalogResult::DeviceDisconnected));
+        assert_eq!(plugin.read_analog(S as u16, first).0, Err(WootingAnalogResult::NoDevices));
+        assert_eq!(plugin.read_analog(S as u16, 0).0, Ok(0.0));
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[2], (DeviceEventType::Disconnected, id) if id == first));
+    }
+
+    #[test]
+    fn test_uninitialised() {
+        let mut plugin = MagnetoPlugin::with_source(Desk::default());
+        assert_eq!(plugin.read_analog(S as u16, 0).0, Err(WootingAnalogResult::UnInitialized));
+    }
+}
@@ -0,0 +1,167 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use hidapi::HidApi;
+use rusb::{Context, Device, UsbContext};
+use shared::host::{self, find_message_interface, is_analog, AnalogReader};
+use shared::keymap::{Action, KEY_COUNT};
+use shared::message::Message;
+use shared::report::AnalogReport;
+use shared::{PRODUCT_ID, VENDOR_ID};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often the keymap is read again, to follow profile switches.
+const KEYMAP_REFRESH: Duration = Duration::from_secs(2);
+
+/// The pad stopped answering, most likely it was unplugged.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Disconnected;
+
+pub trait Pad {
+    /// How far each key is pressed as of the newest report.
+    fn travel(&mut self) -> Result<AnalogReport, Disconnected>;
+    /// Base layer of the active profile, `None` until it could be read.
+    fn keymap(&mut self) -> Option<[Action; KEY_COUNT]>;
+}
+
+pub trait PadSource {
+    type Pad: Pad;
+
+    /// Serial numbers of the pads plugged in.
+    fn serials(&mut self) -> Vec<String>;
+    fn open(&mut self, serial: &str) -> Option<Self::Pad>;
+}
+
+/// Finds pads through hidapi, reading their keymap over the vendor interface.
+pub struct HidPadSource {
+    api: Option<HidApi>,
+    context: Option<Context>,
+}
+
+impl HidPadSource {
+    pub fn new() -> Self {
+        Self {
+            api: HidApi::new().map_err(|e| log::error!("hidapi unavailable: {e}")).ok(),
+            context: Context::new().map_err(|e| log::error!("libusb unavailable: {e}")).ok(),
+        }
+    }
+}
+
+impl Default for HidPadSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PadSource for HidPadSource {
+    type Pad = HidPad;
+
+    fn serials(&mut self) -> Vec<String> {
+        let Some(api) = &mut self.api else {
+            return Vec::new();
+        };
+        if let Err(e) = api.refresh_devices() {
+            log::warn!("Listing HID devices failed: {e}");
+        }
+        api.device_list()
+            .filter(|d| is_analog(d))
+            .map(|d| d.serial_number().unwrap_or_default().to_owned())
+            .collect()
+    }
+
+    fn open(&mut self, serial: &str) -> Option<HidPad> {
+        let api = self.api.as_ref()?;
+        let analog = AnalogReader::open(api, Some(serial))
+            .map_err(|e| log::warn!("Opening pad {serial} failed: {e}"))
+            .ok()?;
+
+        let keymap = Arc::new(Mutex::new(None));
+        let usb = self.context.as_ref().and_then(|context| find_usb_device(context, serial));
+        let stop = usb.map(|usb| spawn_keymap_reader(usb, keymap.clone()));
+
+        Some(HidPad { analog, keymap, _stop: stop })
+    }
+}
+
+pub struct HidPad {
+    analog: AnalogReader,
+    /// Kept up to date by a thread, so reading depths never waits on the vendor interface.
+    keymap: Arc<Mutex<Option<[Action; KEY_COUNT]>>>,
+    /// Dropped with the pad, which ends the thread.
+    _stop: Option<Sender<()>>,
+}
+
+impl Pad for HidPad {
+    fn travel(&mut self) -> Result<AnalogReport, Disconnected> {
+        self.analog.travel().cloned().map_err(|_| Disconnected)
+    }
+
+    fn keymap(&mut self) -> Option<[Action; KEY_COUNT]> {
+        *self.keymap.lock().unwrap()
+    }
+}
+
+/// Reads the keymap of `usb` every [`KEYMAP_REFRESH`] until the returned sender is dropped.
+fn spawn_keymap_reader(usb: Device<Context>, keymap: Arc<Mutex<Option<[Action; KEY_COUNT]>>>) -> Sender<()> {
+    let (stop, stopped) = mpsc::channel();
+    thread::spawn(move || loop {
+        // The CLI or its daemon may hold the interface, the last keymap read stays good until then
+        match read_keymap(&usb) {
+            Ok(read) => *keymap.lock().unwrap() = Some(read),
+            Err(e) => log::debug!("Reading the keymap failed: {e}"),
+        }
+        if stopped.recv_timeout(KEYMAP_REFRESH) != Err(RecvTimeoutError::Timeout) {
+            break;
+        }
+    });
+    stop
+}
+
+fn find_usb_device(context: &Context, serial: &str) -> Option<Device<Context>> {
+    context.devices().ok()?.iter().find(|device| {
+        let Ok(descriptor) = device.device_descriptor() else {
+            return false;
+        };
+        if (descriptor.vendor_id(), descriptor.product_id()) != (VENDOR_ID, PRODUCT_ID) {
+            return false;
+        }
+        device.open()
+            .and_then(|handle| handle.read_serial_number_string_ascii(&descriptor))
+            .is_ok_and(|s| s == serial)
+    })
+}
+
+/// Base layer of the active profile, asked for the way the CLI does.
+fn read_keymap(device: &Device<Context>) -> rusb::Result<[Action; KEY_COUNT]> {
+    let (interface, ep_in, ep_out) = find_message_interface(device)?;
+    let handle = device.open()?;
+    handle.claim_interface(interface)?;
+
+    let request = |msg: &Message| host::request(&handle, ep_in, ep_out, msg, REQUEST_TIMEOUT);
+    let keymap = (|| {
+        match request(&Message::GetDeviceInfo)? {
+            Message::DeviceInfo { keys, .. } if keys as usize == KEY_COUNT => {}
+            Message::DeviceInfo { keys, .. } => {
+                log::warn!("Pad has {keys} keys, the plugin was built for {KEY_COUNT}");
+                return Err(rusb::Error::NotSupported);
+            }
+            _ => return Err(rusb::Error::Other),
+        }
+        let profile = match request(&Message::GetProfile)? {
+            Message::ActiveProfile(profile) => profile,
+            _ => return Err(rusb::Error::Other),
+        };
+        let mut keymap = [Action::NoOp; KEY_COUNT];
+        for (key, action) in keymap.iter_mut().enumerate() {
+            match request(&Message::GetAction { profile, layer: 0, key: key as u8 })? {
+                Message::Action { action: a, .. } => *action = a,
+                _ => return Err(rusb::Error::Other),
+            }
+        }
+        Ok(keymap)
+    })();
+
+    let _ = handle.release_interface(interface);
+    keymap
+}
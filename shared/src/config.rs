@@ -0,0 +1,322 @@
+use musli::{Decode, Encode};
+use crate::filter::Filter;
+
+/// Resolution the values of [`KeyConfig`] and [`Calibration`] are given in, see [`scale`].
+pub const REFERENCE_BITS: u8 = 12;
+
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct KeyConfig {
+	pub mode: KeyMode,
+	pub filter: Filter,
+	/// How far thresholds may follow the resting reading as it drifts, 0 to disable.
+	pub max_drift: u16,
+}
+
+/// How a key decides whether it is pressed, values are ADC readings at [`REFERENCE_BITS`].
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum KeyMode {
+	// distance 0 - 400 (think about bigger range)
+	Threshold(Threshold),
+	RapidTrigger(u16),
+}
+
+/// Readings drop as the key goes down, so the release point sits above the actuation point.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
+pub struct Threshold {
+	/// Pressed once the reading drops below this.
+	pub actuation: u16,
+	/// Released once the reading rises above this again.
+	pub release: u16,
+	/// Readings this close to rest count as fully released, 0 to disable.
+	#[cfg_attr(feature = "std", serde(default, skip_serializing_if = "is_zero"))]
+	pub top_dead_zone: u16,
+	/// Readings this close to bottom out count as fully pressed, 0 to disable.
+	#[cfg_attr(feature = "std", serde(default, skip_serializing_if = "is_zero"))]
+	pub bottom_dead_zone: u16,
+}
+
+#[cfg(feature = "std")]
+fn is_zero(v: &u16) -> bool {
+	*v == 0
+}
+
+impl Threshold {
+	/// A single point for pressing and releasing, without dead zones.
+	pub const fn at(point: u16) -> Self {
+		Self {
+			actuation: point,
+			release: point,
+			top_dead_zone: 0,
+			bottom_dead_zone: 0,
+		}
+	}
+}
+
+impl KeyConfig {
+	/// Moves the values from [`REFERENCE_BITS`] to readings of `bits`.
+	pub fn scaled(self, bits: u8) -> Self {
+		let mode = match self.mode {
+			KeyMode::Threshold(t) => KeyMode::Threshold(Threshold {
+				actuation: scale(t.actuation, bits),
+				release: scale(t.release, bits),
+				top_dead_zone: scale(t.top_dead_zone, bits),
+				bottom_dead_zone: scale(t.bottom_dead_zone, bits),
+			}),
+			KeyMode::RapidTrigger(distance) => KeyMode::RapidTrigger(scale(distance, bits)),
+		};
+		Self {
+			mode,
+			max_drift: scale(self.max_drift, bits),
+			..self
+		}
+	}
+
+	pub const DEFAULT: Self = Self {
+		mode: KeyMode::Threshold(Threshold {
+			actuation: 1500,
+			release: 1600,
+			top_dead_zone: 0,
+			bottom_dead_zone: 0,
+		}),
+		filter: Filter::Sma(8),
+		max_drift: 150,
+	};
+}
+
+impl Default for KeyConfig {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+/// How the multiplexed sensors are read, shared by every key.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct ScanConfig {
+	/// Wait after switching the multiplexers before converting, for the sensor to drive the new channel.
+	pub settle_ns: u16,
+	pub sample_time: SampleTime,
+	pub resolution: Resolution,
+	pub oversampling: Oversampling,
+	/// Scans per second, 0 to scan as fast as the ADC allows.
+	pub rate_hz: u16,
+	/// Scan every `idle_interval_ms` once no key moved for this long, 0 to always scan at full rate.
+	pub idle_after_s: u16,
+	pub idle_interval_ms: u16,
+}
+
+/// ADC cycles spent charging the sampling capacitor, longer is quieter for high impedance sensors.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum SampleTime {
+	Cycles3,
+	Cycles15,
+	Cycles28,
+	Cycles56,
+	Cycles84,
+	Cycles112,
+	Cycles144,
+	Cycles480,
+}
+
+impl SampleTime {
+	pub const fn cycles(self) -> u32 {
+		match self {
+			SampleTime::Cycles3 => 3,
+			SampleTime::Cycles15 => 15,
+			SampleTime::Cycles28 => 28,
+			SampleTime::Cycles56 => 56,
+			SampleTime::Cycles84 => 84,
+			SampleTime::Cycles112 => 112,
+			SampleTime::Cycles144 => 144,
+			SampleTime::Cycles480 => 480,
+		}
+	}
+}
+
+/// Bits per conversion, fewer convert faster.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Resolution {
+	Bits12,
+	Bits10,
+	Bits8,
+	Bits6,
+}
+
+impl Resolution {
+	pub const fn bits(self) -> u8 {
+		match self {
+			Resolution::Bits12 => 12,
+			Resolution::Bits10 => 10,
+			Resolution::Bits8 => 8,
+			Resolution::Bits6 => 6,
+		}
+	}
+}
+
+/// Most conversions a single sensor reading can be made of.
+pub const MAX_SAMPLES: usize = 16;
+
+/// Several conversions per sensor, combined into one less noisy reading.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Oversampling {
+	None,
+	/// Average of n conversions, up to [`MAX_SAMPLES`].
+	Average(u8),
+	/// Sum of 4^n conversions shifted right by n, for n more bits. Up to 2.
+	Decimate(u8),
+}
+
+impl Oversampling {
+	/// Conversions per reading.
+	pub fn samples(self) -> usize {
+		match self {
+			Oversampling::None => 1,
+			Oversampling::Average(n) => (n as usize).clamp(1, MAX_SAMPLES),
+			Oversampling::Decimate(_) => 1 << (2 * self.bits()),
+		}
+	}
+
+	/// Resolution gained on top of the ADC's.
+	pub fn bits(self) -> u8 {
+		match self {
+			Oversampling::Decimate(n) => n.min(2),
+			_ => 0,
+		}
+	}
+
+	/// Turns [`Oversampling::samples`] conversions into a reading.
+	pub fn combine(self, samples: &[u16]) -> u16 {
+		let sum: u32 = samples.iter().map(|s| *s as u32).sum();
+		match self {
+			Oversampling::Decimate(_) => (sum >> self.bits()) as u16,
+			_ => (sum / samples.len().max(1) as u32) as u16,
+		}
+	}
+}
+
+impl ScanConfig {
+	/// Effective resolution of the readings.
+	pub fn bits(&self) -> u8 {
+		self.resolution.bits() + self.oversampling.bits()
+	}
+
+	pub const DEFAULT: Self = Self {
+		settle_ns: 1000,
+		sample_time: SampleTime::Cycles56,
+		resolution: Resolution::Bits12,
+		oversampling: Oversampling::None,
+		rate_hz: 8000,
+		idle_after_s: 60,
+		idle_interval_ms: 10,
+	};
+}
+
+impl Default for ScanConfig {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+/// How the pad presents itself to the host, changes take effect once it enumerated again.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct UsbConfig {
+	/// Interval the host polls the keyboard for reports at, 1 to 255.
+	pub poll_ms: u8,
+	pub rollover: Rollover,
+}
+
+/// How many keys the keyboard reports at once.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Rollover {
+	/// Six keys and the modifiers, in the boot report. For hosts that don't parse report descriptors.
+	SixKey,
+	/// Every key. Hosts asking for the boot protocol still get six.
+	NKey,
+}
+
+impl UsbConfig {
+	pub const DEFAULT: Self = Self {
+		poll_ms: 1,
+		rollover: Rollover::NKey,
+	};
+}
+
+impl Default for UsbConfig {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+/// Fixed readings for the ends of a key's travel, replacing the ones learned while scanning.
+#[derive(Debug, Default, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
+pub struct Calibration {
+	/// Reading of the released key.
+	#[cfg_attr(feature = "std", serde(default, skip_serializing_if = "Option::is_none"))]
+	pub rest: Option<u16>,
+	/// Reading of the fully pressed key.
+	#[cfg_attr(feature = "std", serde(default, skip_serializing_if = "Option::is_none"))]
+	pub bottom: Option<u16>,
+}
+
+impl Calibration {
+	pub fn is_empty(&self) -> bool {
+		self.rest.is_none() && self.bottom.is_none()
+	}
+
+	/// Moves the readings from [`REFERENCE_BITS`] to `bits`.
+	pub fn scaled(self, bits: u8) -> Self {
+		Self {
+			rest: self.rest.map(|v| scale(v, bits)),
+			bottom: self.bottom.map(|v| scale(v, bits)),
+		}
+	}
+}
+
+/// Moves a reading at [`REFERENCE_BITS`] to one at `bits`.
+pub const fn scale(value: u16, bits: u8) -> u16 {
+	let scaled = if bits >= REFERENCE_BITS {
+		(value as u32) << (bits - REFERENCE_BITS)
+	} else {
+		(value as u32) >> (REFERENCE_BITS - bits)
+	};
+	if scaled > u16::MAX as u32 { u16::MAX } else { scaled as u16 }
+}
+
+/// One past the largest reading at `bits`, reads as a released key.
+pub const fn full_scale(bits: u8) -> u16 {
+	let full = 1u32 << bits;
+	if full > u16::MAX as u32 { u16::MAX } else { full as u16 }
+}
+
+#[cfg(test)]
+mod test {
+	use crate::config::{scale, Oversampling};
+
+	#[test]
+	fn test_scale() {
+		assert_eq!(scale(1500, 12), 1500);
+		assert_eq!(scale(1500, 14), 6000);
+		assert_eq!(scale(1500, 10), 375);
+	}
+
+	#[test]
+	fn test_oversampling() {
+		assert_eq!(Oversampling::Average(40).samples(), 16);
+		assert_eq!(Oversampling::Average(4).combine(&[100, 101, 102, 103]), 101);
+
+		// The noise between conversions is what the extra bits are made of
+		let decimate = Oversampling::Decimate(2);
+		assert_eq!(decimate.samples(), 16);
+		let samples = [100, 101].repeat(8);
+		assert_eq!(decimate.combine(&samples), 402);
+	}
+}
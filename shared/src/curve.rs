@@ -0,0 +1,99 @@
+//! Response curves turning how far a key is pressed into how strongly an analog output reacts.
+
+use musli::{Decode, Encode};
+use crate::key::FULL_TRAVEL;
+
+/// Shape of the response between the dead zones, input and output both go up to [`FULL_TRAVEL`].
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Curve {
+	Linear,
+	/// Gentle near the top of travel, for fine control with light presses.
+	Quadratic,
+	Cubic,
+	/// Output at 0, 25, 50, 75 and 100% of the input, straight lines in between.
+	Points([u16; 5]),
+}
+
+impl Curve {
+	pub fn apply(self, input: u16) -> u16 {
+		let full = FULL_TRAVEL as u32;
+		let x = input.min(FULL_TRAVEL) as u32;
+		let output = match self {
+			Curve::Linear => x,
+			Curve::Quadratic => x * x / full,
+			Curve::Cubic => x * x * x / (full * full),
+			Curve::Points(points) => {
+				let step = full / 4;
+				let segment = (x / step).min(3) as usize;
+				let (from, to) = (points[segment] as i32, points[segment + 1] as i32);
+				let t = (x - segment as u32 * step) as i32;
+				(from + (to - from) * t / step as i32).max(0) as u32
+			}
+		};
+		output.min(full) as u16
+	}
+}
+
+/// How an analog output follows a key, from the top of its travel down.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct Response {
+	/// Travel before the output starts to move.
+	pub dead_zone: u16,
+	/// Travel at the bottom that already gives the full output.
+	pub outer_dead_zone: u16,
+	pub curve: Curve,
+}
+
+impl Response {
+	pub const DEFAULT: Self = Self {
+		dead_zone: 0,
+		outer_dead_zone: 0,
+		curve: Curve::Linear,
+	};
+
+	/// Output for a key at `travel`, both up to [`FULL_TRAVEL`].
+	pub fn apply(&self, travel: u16) -> u16 {
+		let span = FULL_TRAVEL.saturating_sub(self.dead_zone).saturating_sub(self.outer_dead_zone).max(1) as u32;
+		let input = (travel.saturating_sub(self.dead_zone) as u32).min(span) * FULL_TRAVEL as u32 / span;
+		self.curve.apply(input as u16)
+	}
+}
+
+impl Default for Response {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::curve::{Curve, Response};
+	use crate::key::FULL_TRAVEL;
+
+	#[test]
+	fn test_curves() {
+		assert_eq!(Curve::Linear.apply(500), 500);
+		assert_eq!(Curve::Quadratic.apply(500), 250);
+		assert_eq!(Curve::Cubic.apply(500), 125);
+		for curve in [Curve::Linear, Curve::Quadratic, Curve::Cubic] {
+			assert_eq!(curve.apply(0), 0);
+			assert_eq!(curve.apply(FULL_TRAVEL), FULL_TRAVEL);
+		}
+
+		let points = Curve::Points([0, 500, 600, 700, 1000]);
+		assert_eq!(points.apply(125), 250);
+		assert_eq!(points.apply(500), 600);
+		assert_eq!(points.apply(FULL_TRAVEL), 1000);
+	}
+
+	#[test]
+	fn test_dead_zones() {
+		let response = Response { dead_zone: 100, outer_dead_zone: 100, curve: Curve::Linear };
+		assert_eq!(response.apply(100), 0);
+		assert_eq!(response.apply(500), 500);
+		assert_eq!(response.apply(900), FULL_TRAVEL);
+		assert_eq!(response.apply(FULL_TRAVEL), FULL_TRAVEL);
+	}
+}
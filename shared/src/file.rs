@@ -0,0 +1,470 @@
+//! Human readable form of [`Settings`], meant to be kept in version control.
+//!
+//! ```toml
+//! [[profile]]
+//! name = "default"
+//! layers = [
+//!     ["A", "S", "D", "Layer(1)"],
+//!     ["Left", "Down", "Right", "Trans"],
+//! ]
+//!
+//! [[profile]]
+//! name = "game"
+//! layers = [["NoOp", "NoOp", "NoOp", "Space"]]
+//! gamepad = [
+//!     { key = 0, target = "LeftX-", dead_zone = 50, curve = "quadratic" },
+//!     { key = 1, target = "LeftY+", dead_zone = 50, curve = "quadratic" },
+//!     { key = 2, target = "LeftX+", dead_zone = 50, curve = "quadratic" },
+//!     { key = 3, target = "Button(1)" },
+//! ]
+//!
+//! [[profile]]
+//! name = "drums"
+//! notes = [{ key = 0, note = 36 }, { key = 1, note = 38 }, { key = 2, note = 42 }, { key = 3, note = 46 }]
+//! midi = { channel = 9, fastest_us = 3000, slowest_us = 60000, velocity = "quadratic", aftertouch = true }
+//!
+//! [[key]]
+//! mode = { threshold = { actuation = 1500, release = 1600, top_dead_zone = 50 } }
+//! filter = { ema = { alpha = 64 } }
+//! max_drift = 150
+//! calibration = { rest = 2100, bottom = 900 }
+//!
+//! [scan]
+//! settle_ns = 1000
+//! sample_time = "cycles56"
+//! rate_hz = 8000
+//! idle_after_s = 60
+//! oversampling = { decimate = 1 }
+//!
+//! [usb]
+//! poll_ms = 1
+//! rollover = "n_key"
+//!
+//! [mouse]
+//! speed = 1500
+//! response = { dead_zone = 50, curve = "quadratic" }
+//! ```
+//!
+//! Layers list every key of the layout, row by row. Gamepad bindings and notes only list the keys that have one.
+//! Anything left out is reset: missing profiles are emptied, missing layers are transparent,
+//! missing keys get the default [`KeyConfig`] without calibration, the other sections their defaults.
+
+use std::fmt::{self, Display, Formatter};
+use std::string::String;
+use std::vec::Vec;
+use serde::{Deserialize, Serialize};
+use crate::config::{Calibration, KeyConfig, ScanConfig, UsbConfig};
+use crate::curve::Response;
+use crate::gamepad::{GamepadBinding, GamepadTarget};
+use crate::keymap::{Action, Keymap, EMPTY_KEYMAP, KEY_COUNT, LAYER_COUNT, PROFILE_COUNT, PROFILE_NAME_LEN};
+use crate::midi::MidiConfig;
+use crate::mouse::MouseConfig;
+use crate::settings::{encode_name, Profile, Settings};
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PadConfig {
+	#[serde(default, rename = "profile")]
+	pub profiles: Vec<ProfileConfig>,
+	#[serde(default, rename = "key")]
+	pub keys: Vec<KeyEntry>,
+	#[serde(default)]
+	pub scan: ScanConfig,
+	#[serde(default)]
+	pub usb: UsbConfig,
+	#[serde(default)]
+	pub mouse: MouseConfig,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProfileConfig {
+	#[serde(default)]
+	pub name: String,
+	#[serde(default)]
+	pub layers: Vec<Vec<Action>>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub gamepad: Vec<GamepadEntry>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub notes: Vec<NoteEntry>,
+	#[serde(default, skip_serializing_if = "is_default_midi")]
+	pub midi: MidiConfig,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoteEntry {
+	pub key: usize,
+	pub note: u8,
+}
+
+fn is_default_midi(midi: &MidiConfig) -> bool {
+	*midi == MidiConfig::DEFAULT
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct GamepadEntry {
+	pub key: usize,
+	pub target: GamepadTarget,
+	#[serde(flatten)]
+	pub response: Response,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyEntry {
+	#[serde(flatten)]
+	pub config: KeyConfig,
+	#[serde(default, skip_serializing_if = "Calibration::is_empty")]
+	pub calibration: Calibration,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfigError {
+	TooManyProfiles(usize),
+	TooManyLayers { profile: usize },
+	/// Every layer has to list all keys.
+	KeyCount { profile: usize, layer: usize, len: usize },
+	TooManyKeys(usize),
+	NameTooLong { profile: usize },
+	GamepadKey { profile: usize, key: usize },
+	NoteKey { profile: usize, key: usize },
+	/// Notes go up to 127 and channels to 15.
+	MidiRange { profile: usize },
+	/// An action the pad would refuse, see [`Action::is_valid`].
+	InvalidAction { profile: usize, layer: usize, key: usize },
+}
+
+impl Display for ConfigError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			ConfigError::TooManyProfiles(n) => write!(f, "{n} profiles given, the pad has {PROFILE_COUNT}"),
+			ConfigError::TooManyLayers { profile } => {
+				write!(f, "profile {profile} has more than {LAYER_COUNT} layers")
+			}
+			ConfigError::KeyCount { profile, layer, len } => {
+				write!(f, "profile {profile} layer {layer} has {len} keys, the pad has {KEY_COUNT}")
+			}
+			ConfigError::TooManyKeys(n) => write!(f, "{n} keys given, the pad has {KEY_COUNT}"),
+			ConfigError::NameTooLong { profile } => {
+				write!(f, "name of profile {profile} is longer than {PROFILE_NAME_LEN} bytes")
+			}
+			ConfigError::GamepadKey { profile, key } => {
+				write!(f, "profile {profile} binds key {key} to the gamepad, the pad has {KEY_COUNT}")
+			}
+			ConfigError::NoteKey { profile, key } => {
+				write!(f, "profile {profile} gives key {key} a note, the pad has {KEY_COUNT}")
+			}
+			ConfigError::MidiRange { profile } => {
+				write!(f, "profile {profile} has a note above 127 or a channel above 15")
+			}
+			ConfigError::InvalidAction { profile, layer, key } => {
+				write!(f, "profile {profile} layer {layer} key {key} has an action the pad can't send")
+			}
+		}
+	}
+}
+
+impl PadConfig {
+	pub fn to_settings(&self) -> Result<Settings, ConfigError> {
+		if self.profiles.len() > PROFILE_COUNT {
+			return Err(ConfigError::TooManyProfiles(self.profiles.len()));
+		}
+		if self.keys.len() > KEY_COUNT {
+			return Err(ConfigError::TooManyKeys(self.keys.len()));
+		}
+
+		let mut settings = Settings::empty();
+		for (i, profile) in self.profiles.iter().enumerate() {
+			if profile.name.len() > PROFILE_NAME_LEN {
+				return Err(ConfigError::NameTooLong { profile: i });
+			}
+			if profile.layers.len() > LAYER_COUNT {
+				return Err(ConfigError::TooManyLayers { profile: i });
+			}
+
+			let target = &mut settings.profiles[i];
+			target.name = encode_name(&profile.name);
+			for (l, layer) in profile.layers.iter().enumerate() {
+				target.keymap[l] = layer.as_slice().try_into().map_err(|_| ConfigError::KeyCount {
+					profile: i,
+					layer: l,
+					len: layer.len(),
+				})?;
+				if let Some(key) = layer.iter().position(|a| !a.is_valid()) {
+					return Err(ConfigError::InvalidAction { profile: i, layer: l, key });
+				}
+			}
+			for entry in &profile.gamepad {
+				let binding = target.gamepad.get_mut(entry.key).ok_or(ConfigError::GamepadKey { profile: i, key: entry.key })?;
+				*binding = Some(GamepadBinding { target: entry.target, response: entry.response });
+			}
+			if profile.midi.channel > 15 || profile.notes.iter().any(|n| n.note > 127) {
+				return Err(ConfigError::MidiRange { profile: i });
+			}
+			for entry in &profile.notes {
+				let note = target.notes.get_mut(entry.key).ok_or(ConfigError::NoteKey { profile: i, key: entry.key })?;
+				*note = Some(entry.note);
+			}
+			target.midi = profile.midi;
+		}
+		for (i, key) in self.keys.iter().enumerate() {
+			settings.keys[i] = key.config;
+			settings.calibration[i] = key.calibration;
+		}
+		settings.scan = self.scan;
+		settings.usb = self.usb;
+		settings.mouse = self.mouse;
+		Ok(settings)
+	}
+
+	/// Leaves out trailing profiles and layers that are empty.
+	pub fn from_settings(settings: &Settings) -> Self {
+		let mut profiles: Vec<_> = settings.profiles.iter().map(ProfileConfig::from_profile).collect();
+		while profiles.last().is_some_and(|p| *p == ProfileConfig::default()) {
+			profiles.pop();
+		}
+
+		let keys = settings.keys.iter()
+			.zip(&settings.calibration)
+			.map(|(config, calibration)| KeyEntry { config: *config, calibration: *calibration })
+			.collect();
+
+		Self { profiles, keys, scan: settings.scan, usb: settings.usb, mouse: settings.mouse }
+	}
+}
+
+impl ProfileConfig {
+	fn from_profile(profile: &Profile) -> Self {
+		let mut layers: Vec<Vec<Action>> = profile.keymap.iter().map(|l| l.to_vec()).collect();
+		while !layers.is_empty() && layers[layers.len() - 1] == EMPTY_KEYMAP[layers.len() - 1] {
+			layers.pop();
+		}
+
+		let gamepad = profile.gamepad.iter()
+			.enumerate()
+			.filter_map(|(key, binding)| binding.map(|b| GamepadEntry { key, target: b.target, response: b.response }))
+			.collect();
+
+		let notes = profile.notes.iter()
+			.enumerate()
+			.filter_map(|(key, note)| note.map(|note| NoteEntry { key, note }))
+			.collect();
+
+		Self {
+			name: profile.name().into(),
+			layers,
+			gamepad,
+			notes,
+			midi: profile.midi,
+		}
+	}
+}
+
+/// One difference between two settings, for showing the user.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+	ProfileName { profile: usize, from: String, to: String },
+	Action { profile: usize, layer: usize, key: usize, from: Action, to: Action },
+	GamepadBinding { profile: usize, key: usize, from: Option<GamepadBinding>, to: Option<GamepadBinding> },
+	Midi { profile: usize, from: MidiConfig, to: MidiConfig },
+	Note { profile: usize, key: usize, from: Option<u8>, to: Option<u8> },
+	KeyConfig { key: usize, from: KeyConfig, to: KeyConfig },
+	Calibration { key: usize, from: Calibration, to: Calibration },
+	Scan { from: ScanConfig, to: ScanConfig },
+	/// Makes the pad enumerate again once saved.
+	Usb { from: UsbConfig, to: UsbConfig },
+	Mouse { from: MouseConfig, to: MouseConfig },
+}
+
+impl Display for Change {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Change::ProfileName { profile, from, to } => {
+				write!(f, "profile {profile} name: {from:?} -> {to:?}")
+			}
+			Change::Action { profile, layer, key, from, to } => {
+				write!(f, "profile {profile} layer {layer} key {key}: {from} -> {to}")
+			}
+			Change::GamepadBinding { profile, key, from, to } => {
+				write!(f, "profile {profile} key {key} gamepad: {from:?} -> {to:?}")
+			}
+			Change::Midi { profile, from, to } => write!(f, "profile {profile} midi: {from:?} -> {to:?}"),
+			Change::Note { profile, key, from, to } => {
+				write!(f, "profile {profile} key {key} note: {from:?} -> {to:?}")
+			}
+			Change::KeyConfig { key, from, to } => write!(f, "key {key} config: {from:?} -> {to:?}"),
+			Change::Calibration { key, from, to } => write!(f, "key {key} calibration: {from:?} -> {to:?}"),
+			Change::Scan { from, to } => write!(f, "scan: {from:?} -> {to:?}"),
+			Change::Usb { from, to } => write!(f, "usb: {from:?} -> {to:?}"),
+			Change::Mouse { from, to } => write!(f, "mouse: {from:?} -> {to:?}"),
+		}
+	}
+}
+
+/// What has to change to turn `from` into `to`.
+pub fn diff(from: &Settings, to: &Settings) -> Vec<Change> {
+	let mut changes = Vec::new();
+	for (profile, (a, b)) in from.profiles.iter().zip(&to.profiles).enumerate() {
+		if a.name != b.name {
+			changes.push(Change::ProfileName { profile, from: a.name().into(), to: b.name().into() });
+		}
+		changes.extend(diff_keymap(&a.keymap, &b.keymap).map(|(layer, key, from, to)| {
+			Change::Action { profile, layer, key, from, to }
+		}));
+		for key in 0..KEY_COUNT {
+			if a.gamepad[key] != b.gamepad[key] {
+				changes.push(Change::GamepadBinding { profile, key, from: a.gamepad[key], to: b.gamepad[key] });
+			}
+			if a.notes[key] != b.notes[key] {
+				changes.push(Change::Note { profile, key, from: a.notes[key], to: b.notes[key] });
+			}
+		}
+		if a.midi != b.midi {
+			changes.push(Change::Midi { profile, from: a.midi, to: b.midi });
+		}
+	}
+	for key in 0..KEY_COUNT {
+		if from.keys[key] != to.keys[key] {
+			changes.push(Change::KeyConfig { key, from: from.keys[key], to: to.keys[key] });
+		}
+		if from.calibration[key] != to.calibration[key] {
+			changes.push(Change::Calibration { key, from: from.calibration[key], to: to.calibration[key] });
+		}
+	}
+	if from.scan != to.scan {
+		changes.push(Change::Scan { from: from.scan, to: to.scan });
+	}
+	if from.usb != to.usb {
+		changes.push(Change::Usb { from: from.usb, to: to.usb });
+	}
+	if from.mouse != to.mouse {
+		changes.push(Change::Mouse { from: from.mouse, to: to.mouse });
+	}
+	changes
+}
+
+fn diff_keymap<'a>(a: &'a Keymap, b: &'a Keymap) -> impl Iterator<Item = (usize, usize, Action, Action)> + 'a {
+	(0..LAYER_COUNT)
+		.flat_map(|layer| (0..KEY_COUNT).map(move |key| (layer, key)))
+		.filter(|(layer, key)| a[*layer][*key] != b[*layer][*key])
+		.map(|(layer, key)| (layer, key, a[layer][key], b[layer][key]))
+}
+
+#[cfg(test)]
+mod test {
+	use crate::config::{Calibration, KeyConfig, KeyMode, SampleTime, ScanConfig, Threshold, UsbConfig};
+	use crate::curve::{Curve, Response};
+	use crate::file::{diff, Change, ConfigError, PadConfig};
+	use crate::filter::Filter;
+	use crate::gamepad::{GamepadBinding, GamepadTarget, Stick};
+	use crate::keymap::Action;
+	use crate::midi::MidiConfig;
+	use crate::mouse::MouseConfig;
+	use crate::report::CONSUMER_USAGE_MAX;
+
+	const FILE: &str = r#"
+		[[profile]]
+		name = "default"
+		layers = [
+			["A", "S", "D", "Layer(1)"],
+			["Left", "Down", "Right", "Trans"],
+		]
+
+		[[profile]]
+		name = "game"
+		layers = [["Z", "X", "C", "V"]]
+		gamepad = [
+			{ key = 0, target = "LeftY-", dead_zone = 50, curve = "cubic" },
+			{ key = 3, target = "Button(2)" },
+		]
+		notes = [{ key = 1, note = 38 }]
+		midi = { channel = 9, velocity = "cubic" }
+
+		[[key]]
+		mode = { threshold = { actuation = 1400, release = 1450, bottom_dead_zone = 100 } }
+		filter = { median = 5 }
+		calibration = { rest = 2100 }
+
+		[[key]]
+		mode = { threshold = { actuation = 1600, release = 1600 } }
+		max_drift = 0
+
+		[scan]
+		sample_time = "cycles144"
+
+		[usb]
+		poll_ms = 2
+
+		[mouse]
+		speed = 800
+		response = { outer_dead_zone = 100, curve = { points = [0, 100, 300, 600, 1000] } }
+	"#;
+
+	#[test]
+	fn test_parse() {
+		let config: PadConfig = toml::from_str(FILE).unwrap();
+		let settings = config.to_settings().unwrap();
+
+		assert_eq!(settings.profiles[0].keymap[1][0], Action::Key(0x50));
+		assert_eq!(settings.profiles[1].name(), "game");
+		assert_eq!(settings.profiles[1].gamepad[0], Some(GamepadBinding {
+			target: GamepadTarget::Negative(Stick::LeftY),
+			response: Response { dead_zone: 50, curve: Curve::Cubic, ..Response::DEFAULT },
+		}));
+		assert_eq!(settings.profiles[1].gamepad[1], None);
+		assert_eq!(settings.profiles[1].gamepad[3].map(|b| b.target), Some(GamepadTarget::Button(2)));
+		assert!(settings.profiles[0].gamepad.iter().all(Option::is_none));
+		assert_eq!(settings.profiles[1].notes[1], Some(38));
+		assert_eq!(settings.profiles[1].midi, MidiConfig { channel: 9, velocity: Curve::Cubic, ..MidiConfig::DEFAULT });
+		assert_eq!(settings.profiles[0].midi, MidiConfig::DEFAULT);
+		let threshold = Threshold { actuation: 1400, release: 1450, top_dead_zone: 0, bottom_dead_zone: 100 };
+		assert_eq!(settings.keys[0], KeyConfig {
+			mode: KeyMode::Threshold(threshold),
+			filter: Filter::Median(5),
+			..KeyConfig::DEFAULT
+		});
+		assert_eq!(settings.keys[1], KeyConfig {
+			mode: KeyMode::Threshold(Threshold::at(1600)),
+			max_drift: 0,
+			..KeyConfig::DEFAULT
+		});
+		assert_eq!(settings.keys[2], KeyConfig::default());
+		assert_eq!(settings.calibration[0], Calibration { rest: Some(2100), bottom: None });
+		assert_eq!(settings.scan, ScanConfig { sample_time: SampleTime::Cycles144, ..ScanConfig::DEFAULT });
+		assert_eq!(settings.usb, UsbConfig { poll_ms: 2, ..UsbConfig::DEFAULT });
+		assert_eq!(settings.mouse, MouseConfig {
+			speed: 800,
+			response: Response { outer_dead_zone: 100, curve: Curve::Points([0, 100, 300, 600, 1000]), ..Response::DEFAULT },
+			..MouseConfig::DEFAULT
+		});
+	}
+
+	#[test]
+	fn test_invalid_action() {
+		let mut config: PadConfig = toml::from_str(FILE).unwrap();
+		config.profiles[1].layers[0][2] = Action::Consumer(CONSUMER_USAGE_MAX + 1);
+
+		assert_eq!(config.to_settings(), Err(ConfigError::InvalidAction { profile: 1, layer: 0, key: 2 }));
+	}
+
+	#[test]
+	fn test_roundtrip() {
+		let settings = toml::from_str::<PadConfig>(FILE).unwrap().to_settings().unwrap();
+		let text = toml::to_string(&PadConfig::from_settings(&settings)).unwrap();
+		let reparsed = toml::from_str::<PadConfig>(&text).unwrap().to_settings().unwrap();
+
+		assert_eq!(reparsed, settings);
+	}
+
+	#[test]
+	fn test_diff() {
+		let settings = toml::from_str::<PadConfig>(FILE).unwrap().to_settings().unwrap();
+		let mut changed = settings.clone();
+		changed.profiles[1].keymap[0][3] = Action::Key(0x2C);
+
+		assert_eq!(diff(&settings, &changed), [Change::Action {
+			profile: 1,
+			layer: 0,
+			key: 3,
+			from: Action::Key(0x19),
+			to: Action::Key(0x2C),
+		}]);
+	}
+}
@@ -0,0 +1,319 @@
+//! Smoothing of raw sensor readings before they are compared against thresholds.
+
+use musli::{Decode, Encode};
+
+/// Longest history a [`Filter`] can look at.
+pub const MAX_WINDOW: usize = 16;
+
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Filter {
+	/// Raw readings, no lag but all of the noise.
+	None,
+	/// Average of the last n readings, lags by about n / 2.
+	Sma(u8),
+	/// Exponential moving average, each reading is weighted with `alpha / 256`.
+	Ema {
+		alpha: u8,
+	},
+	/// Median of the last n readings, drops single spikes without smearing edges.
+	Median(u8),
+	/// One dimensional Kalman filter tracking a constant value.
+	Kalman {
+		/// How much the true value is expected to move between readings, as variance.
+		process_noise: u16,
+		/// Variance of the sensor noise.
+		measurement_noise: u16,
+	},
+}
+
+impl Default for Filter {
+	fn default() -> Self {
+		Filter::Sma(8)
+	}
+}
+
+/// History needed by every kind of [`Filter`], so switching filters needs no new storage.
+#[derive(Debug, Clone, Copy)]
+pub struct FilterState {
+	window: [u16; MAX_WINDOW],
+	/// Where the next reading goes.
+	pos: usize,
+	/// `u32` fits `MAX_WINDOW` readings of any `u16`.
+	sum: u32,
+	/// In 1/256ths.
+	ema: u32,
+	estimate: f32,
+	error: f32,
+}
+
+impl FilterState {
+	/// Pretends `initial` was read for as long as the filter looks back.
+	pub const fn new(initial: u16) -> Self {
+		Self {
+			window: [initial; MAX_WINDOW],
+			pos: 0,
+			sum: initial as u32 * MAX_WINDOW as u32,
+			ema: (initial as u32) << 8,
+			estimate: initial as f32,
+			error: 0.0,
+		}
+	}
+
+	pub fn update(&mut self, filter: Filter, value: u16) -> u16 {
+		self.sum = self.sum - self.window[self.pos] as u32 + value as u32;
+		self.window[self.pos] = value;
+		self.pos = (self.pos + 1) % MAX_WINDOW;
+
+		match filter {
+			Filter::None => value,
+			Filter::Sma(n) => {
+				let n = clamp_window(n);
+				if n == MAX_WINDOW {
+					return (self.sum / MAX_WINDOW as u32) as u16;
+				}
+				let sum: u32 = self.last(n).map(|v| v as u32).sum();
+				(sum / n as u32) as u16
+			}
+			Filter::Ema { alpha } => {
+				let alpha = alpha.max(1) as i64;
+				let target = (value as i64) << 8;
+				let ema = self.ema as i64;
+				self.ema = (ema + (target - ema) * alpha / 256) as u32;
+				((self.ema + 128) >> 8) as u16
+			}
+			Filter::Median(n) => {
+				let n = clamp_window(n);
+				let mut sorted = [0; MAX_WINDOW];
+				for (slot, v) in sorted.iter_mut().zip(self.last(n)) {
+					*slot = v;
+				}
+				let sorted = &mut sorted[..n];
+				sorted.sort_unstable();
+				sorted[n / 2]
+			}
+			Filter::Kalman { process_noise, measurement_noise } => {
+				let error = self.error + process_noise as f32;
+				let gain = error / (error + (measurement_noise as f32).max(1.0));
+				self.estimate += gain * (value as f32 - self.estimate);
+				self.error = (1.0 - gain) * error;
+				(self.estimate + 0.5) as u16
+			}
+		}
+	}
+
+	/// The last `n` readings, newest first.
+	fn last(&self, n: usize) -> impl Iterator<Item = u16> + '_ {
+		(1..=n).map(move |i| self.window[(self.pos + MAX_WINDOW - i) % MAX_WINDOW])
+	}
+}
+
+fn clamp_window(n: u8) -> usize {
+	(n as usize).clamp(1, MAX_WINDOW)
+}
+
+#[cfg(test)]
+mod test {
+	use std::path::Path;
+	use std::string::String;
+	use std::vec::Vec;
+	use std::{fs, println};
+	use crate::config::{Calibration, KeyConfig, KeyMode, REFERENCE_BITS};
+	use crate::filter::{Filter, FilterState, MAX_WINDOW};
+	use crate::key::KeyState;
+
+	const REST: u16 = 2900;
+	const BOTTOM: u16 = 1100;
+	const THRESHOLD: u16 = 2000;
+	const PRESS_AT: usize = 200;
+
+	/// A synthetic press, not a recording: noisy rest, a fast stroke, a hold and a release,
+	/// with the odd spike standing in for mux switching.
+	fn trace() -> Vec<u16> {
+		let mut seed = 0x1234_5678u32;
+		let mut noise = move || {
+			seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+			((seed >> 16) % 41) as i32 - 20
+		};
+
+		(0..600)
+			.map(|i| {
+				let clean = match i {
+					_ if i < PRESS_AT => REST as i32,
+					_ if i < PRESS_AT + 8 => REST as i32 - (REST - BOTTOM) as i32 * (i - PRESS_AT) as i32 / 8,
+					_ if i < 400 => BOTTOM as i32,
+					_ if i < 408 => BOTTOM as i32 + (REST - BOTTOM) as i32 * (i - 400) as i32 / 8,
+					_ => REST as i32,
+				};
+				let spike = if i % 97 == 0 { 150 } else { 0 };
+				(clean + noise() + spike) as u16
+			})
+			.collect()
+	}
+
+	/// Samples between the noise free signal and the filtered one crossing the threshold,
+	/// and the mean absolute deviation at rest.
+	fn measure(filter: Filter) -> (usize, f32) {
+		let trace = trace();
+		let mut state = FilterState::new(REST);
+		let out: Vec<u16> = trace.iter().map(|v| state.update(filter, *v)).collect();
+
+		let clean_crossing = PRESS_AT + 5;
+		let crossing = out.iter().position(|v| *v < THRESHOLD).unwrap();
+
+		let rest = &out[20..PRESS_AT];
+		let mean = rest.iter().map(|v| *v as u32).sum::<u32>() / rest.len() as u32;
+		let deviation = rest.iter().map(|v| (*v as u32).abs_diff(mean)).sum::<u32>();
+
+		(crossing.saturating_sub(clean_crossing), deviation as f32 / rest.len() as f32)
+	}
+
+	const FILTERS: [Filter; 6] = [
+		Filter::None,
+		Filter::Sma(8),
+		Filter::Ema { alpha: 64 },
+		Filter::Median(5),
+		Filter::Kalman { process_noise: 64, measurement_noise: 256 },
+		Filter::Sma(MAX_WINDOW as u8),
+	];
+
+	#[test]
+	fn test_compare_filters() {
+		let (raw_lag, raw_noise) = measure(Filter::None);
+		println!("{:?}: lag {} noise {:.1}", Filter::None, raw_lag, raw_noise);
+		assert!(raw_lag <= 1);
+
+		for filter in &FILTERS[1..] {
+			let (lag, noise) = measure(*filter);
+			println!("{filter:?}: lag {lag} noise {noise:.1}");
+
+			assert!(noise < raw_noise, "{filter:?} does not reduce noise");
+			assert!(lag <= MAX_WINDOW, "{filter:?} lags by {lag} samples");
+		}
+
+		// SMA lags by about half of its window
+		assert!((3..=5).contains(&measure(Filter::Sma(8)).0));
+	}
+
+	/// Rapid trigger flips on small movements, so it relies on the filter to keep the noise out.
+	#[test]
+	fn test_rapid_trigger_on_trace() {
+		for filter in [Filter::Median(5), Filter::Sma(8)] {
+			let mut key = KeyState::new(REST);
+			key.configure(
+				KeyConfig { mode: KeyMode::RapidTrigger(200), filter, max_drift: 0 },
+				Calibration::default(),
+				REFERENCE_BITS,
+			);
+			let mut changes = 0;
+			for value in trace() {
+				key.update(value);
+				changes += key.changed as usize;
+			}
+			assert_eq!(changes, 2, "{filter:?}");
+		}
+	}
+
+	/// A trace recorded from a real key, kept in `shared/traces` as one raw reading per line.
+	///
+	/// Lines starting with `#` are headers: `# presses: n` counts the strokes in the trace and
+	/// `# rest: n` the readings before the first one starts.
+	struct Recording {
+		name: String,
+		presses: usize,
+		rest: usize,
+		readings: Vec<u16>,
+	}
+
+	/// Every recording in `shared/traces`, none if there is no such directory.
+	fn recordings() -> Vec<Recording> {
+		let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("traces");
+		let Ok(entries) = fs::read_dir(dir) else {
+			return Vec::new();
+		};
+		let mut recordings: Vec<Recording> = entries
+			.map(|entry| {
+				let path = entry.unwrap().path();
+				let name = path.file_name().unwrap().to_string_lossy().into_owned();
+				let text = fs::read_to_string(&path).unwrap();
+				let mut recording = Recording { name, presses: 0, rest: 0, readings: Vec::new() };
+				for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+					let header = |key: &str| line.strip_prefix('#')?.trim().strip_prefix(key)?.trim().parse().ok();
+					if let Some(presses) = header("presses:") {
+						recording.presses = presses;
+					} else if let Some(rest) = header("rest:") {
+						recording.rest = rest;
+					} else if !line.starts_with('#') {
+						let reading = line.parse().unwrap_or_else(|_| panic!("{}: bad reading {line}", recording.name));
+						recording.readings.push(reading);
+					}
+				}
+				recording
+			})
+			.collect();
+		recordings.sort_by(|a, b| a.name.cmp(&b.name));
+		recordings
+	}
+
+	/// Mean absolute deviation of `filter`'s output over the resting start of `recording`.
+	fn rest_noise(recording: &Recording, filter: Filter) -> f32 {
+		let mut state = FilterState::new(recording.readings[0]);
+		let out: Vec<u16> = recording.readings[..recording.rest].iter().map(|v| state.update(filter, *v)).collect();
+		// The window is still filled with the first reading at the start
+		let rest = &out[MAX_WINDOW.min(out.len() / 2)..];
+		let mean = rest.iter().map(|v| *v as u32).sum::<u32>() / rest.len() as u32;
+		rest.iter().map(|v| (*v as u32).abs_diff(mean)).sum::<u32>() as f32 / rest.len() as f32
+	}
+
+	#[test]
+	fn test_compare_filters_on_recordings() {
+		for recording in recordings() {
+			let raw = rest_noise(&recording, Filter::None);
+			for filter in &FILTERS[1..] {
+				let noise = rest_noise(&recording, *filter);
+				println!("{}: {filter:?} noise {noise:.1}, raw {raw:.1}", recording.name);
+				assert!(noise <= raw, "{}: {filter:?} does not reduce noise", recording.name);
+			}
+		}
+	}
+
+	#[test]
+	fn test_rapid_trigger_on_recordings() {
+		for recording in recordings() {
+			for filter in [Filter::Median(5), Filter::Sma(8)] {
+				let mut key = KeyState::new(recording.readings[0]);
+				key.configure(
+					KeyConfig { mode: KeyMode::RapidTrigger(200), filter, max_drift: 0 },
+					Calibration::default(),
+					REFERENCE_BITS,
+				);
+				let mut changes = 0;
+				for value in &recording.readings {
+					key.update(*value);
+					changes += key.changed as usize;
+				}
+				assert_eq!(changes, 2 * recording.presses, "{}: {filter:?}", recording.name);
+			}
+		}
+	}
+
+	#[test]
+	fn test_settles_on_constant_input() {
+		for filter in FILTERS {
+			let mut state = FilterState::new(4096);
+			let mut out = 0;
+			for _ in 0..200 {
+				out = state.update(filter, 1234);
+			}
+			assert!(out.abs_diff(1234) <= 1, "{filter:?} settled on {out}");
+		}
+	}
+
+	#[test]
+	fn test_no_overflow() {
+		let mut state = FilterState::new(u16::MAX);
+		for filter in FILTERS {
+			assert_eq!(state.update(filter, u16::MAX), u16::MAX, "{filter:?}");
+		}
+	}
+}
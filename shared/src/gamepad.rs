@@ -0,0 +1,186 @@
+//! Keys as gamepad sticks, triggers and buttons, following how far each key is pressed.
+
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+use musli::{Decode, Encode};
+use crate::curve::Response;
+use crate::key::FULL_TRAVEL;
+use crate::report::GamepadReport;
+
+/// Buttons a [`GamepadReport`] has.
+pub const BUTTON_COUNT: u8 = 16;
+/// Full deflection of a stick or trigger.
+const AXIS_MAX: i32 = i16::MAX as i32;
+
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+pub enum Stick {
+	LeftX,
+	LeftY,
+	RightX,
+	RightY,
+}
+
+/// What a key drives on the gamepad.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+pub enum GamepadTarget {
+	/// Towards the positive end of a stick axis, right on X and down on Y.
+	Positive(Stick),
+	Negative(Stick),
+	LeftTrigger,
+	RightTrigger,
+	/// Buttons 1 to [`BUTTON_COUNT`], pressed at the key's actuation point like a keyboard key.
+	Button(u8),
+}
+
+/// A key bound to the gamepad, next to whatever it does in the keymap.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+pub struct GamepadBinding {
+	pub target: GamepadTarget,
+	/// Ignored for buttons.
+	pub response: Response,
+}
+
+const STICK_NAMES: &[(Stick, &str)] = &[
+	(Stick::LeftX, "LeftX"),
+	(Stick::LeftY, "LeftY"),
+	(Stick::RightX, "RightX"),
+	(Stick::RightY, "RightY"),
+];
+
+/// Written as `LeftY-`, `RightX+`, `LeftTrigger` or `Button(1)` in config files.
+impl Display for GamepadTarget {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		let stick = |stick: &Stick| STICK_NAMES.iter().find(|(s, _)| s == stick).map(|(_, n)| *n).unwrap_or_default();
+		match self {
+			GamepadTarget::Positive(s) => write!(f, "{}+", stick(s)),
+			GamepadTarget::Negative(s) => write!(f, "{}-", stick(s)),
+			GamepadTarget::LeftTrigger => f.write_str("LeftTrigger"),
+			GamepadTarget::RightTrigger => f.write_str("RightTrigger"),
+			GamepadTarget::Button(button) => write!(f, "Button({button})"),
+		}
+	}
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseTargetError;
+
+impl FromStr for GamepadTarget {
+	type Err = ParseTargetError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let stick = |name: &str| STICK_NAMES.iter().find(|(_, n)| *n == name).map(|(s, _)| *s).ok_or(ParseTargetError);
+
+		if let Some(name) = s.strip_suffix('+') {
+			Ok(GamepadTarget::Positive(stick(name)?))
+		} else if let Some(name) = s.strip_suffix('-') {
+			Ok(GamepadTarget::Negative(stick(name)?))
+		} else if let Some(arg) = s.strip_prefix("Button(").and_then(|rest| rest.strip_suffix(')')) {
+			match arg.parse() {
+				Ok(button @ 1..=BUTTON_COUNT) => Ok(GamepadTarget::Button(button)),
+				_ => Err(ParseTargetError),
+			}
+		} else {
+			match s {
+				"LeftTrigger" => Ok(GamepadTarget::LeftTrigger),
+				"RightTrigger" => Ok(GamepadTarget::RightTrigger),
+				_ => Err(ParseTargetError),
+			}
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl serde::Serialize for GamepadTarget {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+#[cfg(feature = "std")]
+impl<'de> serde::Deserialize<'de> for GamepadTarget {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+		s.parse().map_err(|_| serde::de::Error::custom(format_args!("unknown gamepad target `{s}`")))
+	}
+}
+
+/// The gamepad state for bound keys, each with its travel and whether it is pressed.
+///
+/// Keys pulling a stick both ways cancel out.
+pub fn report<'a>(keys: impl Iterator<Item = (&'a GamepadBinding, u16, bool)>) -> GamepadReport {
+	let mut sticks = [0i32; 4];
+	let mut triggers = [0i32; 2];
+	let mut buttons = 0u16;
+	for (binding, travel, pressed) in keys {
+		let value = binding.response.apply(travel) as i32 * AXIS_MAX / FULL_TRAVEL as i32;
+		match binding.target {
+			GamepadTarget::Positive(stick) => sticks[stick as usize] += value,
+			GamepadTarget::Negative(stick) => sticks[stick as usize] -= value,
+			GamepadTarget::LeftTrigger => triggers[0] = triggers[0].max(value),
+			GamepadTarget::RightTrigger => triggers[1] = triggers[1].max(value),
+			GamepadTarget::Button(button @ 1..=BUTTON_COUNT) if pressed => buttons |= 1 << (button - 1),
+			GamepadTarget::Button(_) => {}
+		}
+	}
+	GamepadReport {
+		buttons,
+		sticks: sticks.map(|v| v.clamp(-AXIS_MAX, AXIS_MAX) as i16),
+		triggers: triggers.map(|v| v as i16),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::string::ToString;
+	use crate::curve::{Curve, Response};
+	use crate::gamepad::{report, GamepadBinding, GamepadTarget, Stick};
+	use crate::key::FULL_TRAVEL;
+
+	fn bound(target: GamepadTarget) -> GamepadBinding {
+		GamepadBinding { target, response: Response::DEFAULT }
+	}
+
+	#[test]
+	fn test_parse_roundtrip() {
+		let targets = [
+			GamepadTarget::Negative(Stick::LeftY),
+			GamepadTarget::Positive(Stick::RightX),
+			GamepadTarget::LeftTrigger,
+			GamepadTarget::Button(12),
+		];
+		for target in targets {
+			let s = target.to_string();
+			assert_eq!(s.parse(), Ok(target), "{s}");
+		}
+		assert!("Button(17)".parse::<GamepadTarget>().is_err());
+	}
+
+	#[test]
+	fn test_sticks() {
+		let up = bound(GamepadTarget::Negative(Stick::LeftY));
+		let down = bound(GamepadTarget::Positive(Stick::LeftY));
+		let right = GamepadBinding {
+			target: GamepadTarget::Positive(Stick::LeftX),
+			response: Response { dead_zone: 200, curve: Curve::Linear, ..Response::DEFAULT },
+		};
+
+		let state = report([(&up, FULL_TRAVEL, true), (&right, 100, false)].into_iter());
+		assert_eq!(state.sticks, [0, -i16::MAX, 0, 0]);
+
+		let state = report([(&up, FULL_TRAVEL / 2, true), (&down, FULL_TRAVEL, true), (&right, 600, true)].into_iter());
+		assert_eq!(state.sticks[1], i16::MAX - i16::MAX / 2);
+		assert_eq!(state.sticks[0], i16::MAX / 2);
+	}
+
+	#[test]
+	fn test_triggers_and_buttons() {
+		let trigger = bound(GamepadTarget::RightTrigger);
+		let button = bound(GamepadTarget::Button(3));
+
+		let state = report([(&trigger, FULL_TRAVEL / 4, true), (&button, 100, false)].into_iter());
+		assert_eq!((state.triggers, state.buttons), ([0, i16::MAX / 4], 0));
+
+		let state = report([(&button, 100, true)].into_iter());
+		assert_eq!(state.buttons, 0b100);
+	}
+}
@@ -0,0 +1,113 @@
+//! Talking to the pad from a computer, shared by the CLI and the analog plugin.
+
+use std::format;
+use std::time::Duration;
+use hidapi::{DeviceInfo, HidApi, HidDevice, HidError, HidResult};
+use rusb::{Device, DeviceHandle, Direction, TransferType, UsbContext};
+use crate::message::{Message, MESSAGE_BUF_SIZE};
+use crate::report::{AnalogReport, ANALOG_PAGES, ANALOG_PAGE_LEN, ANALOG_REPORT_ID, ANALOG_USAGE_PAGE};
+use crate::{PRODUCT_ID, VENDOR_ID};
+
+/// Vendor class of the interface carrying [`Message`]s, see `WebEndpoints` in the firmware.
+pub const VENDOR_CLASS: u8 = 0xff;
+
+/// Interface number and the bulk IN and OUT endpoints of the [`Message`] interface.
+pub fn find_message_interface<T: UsbContext>(device: &Device<T>) -> rusb::Result<(u8, u8, u8)> {
+	let config = device.active_config_descriptor()?;
+	for interface in config.interfaces() {
+		for alt in interface.descriptors() {
+			if alt.class_code() != VENDOR_CLASS {
+				continue;
+			}
+
+			let bulk = |dir| alt.endpoint_descriptors()
+				.find(|e| e.transfer_type() == TransferType::Bulk && e.direction() == dir)
+				.map(|e| e.address());
+
+			if let (Some(ep_in), Some(ep_out)) = (bulk(Direction::In), bulk(Direction::Out)) {
+				return Ok((alt.interface_number(), ep_in, ep_out));
+			}
+		}
+	}
+	Err(rusb::Error::NotFound)
+}
+
+/// Sends `msg` and waits up to `timeout` for each read of the answer.
+pub fn request<T: UsbContext>(
+	handle: &DeviceHandle<T>,
+	ep_in: u8,
+	ep_out: u8,
+	msg: &Message,
+	timeout: Duration,
+) -> rusb::Result<Message> {
+	handle.write_bulk(ep_out, msg.serialize().as_slice(), timeout)?;
+	loop {
+		let mut buf = [0; MESSAGE_BUF_SIZE];
+		let len = handle.read_bulk(ep_in, &mut buf, timeout)?;
+		// Key events for whoever subscribed to them arrive here too
+		match Message::deserialize(&buf[..len]) {
+			Message::KeyEvent { .. } => continue,
+			answer => return Ok(answer),
+		}
+	}
+}
+
+/// Whether `info` is the analog collection of a pad.
+pub fn is_analog(info: &DeviceInfo) -> bool {
+	info.vendor_id() == VENDOR_ID && info.product_id() == PRODUCT_ID && info.usage_page() == ANALOG_USAGE_PAGE
+}
+
+/// How far each key is pressed, from the vendor defined collection of the HID interface.
+///
+/// Reads through hidraw, which leaves typing and the [`Message`] interface alone.
+pub struct AnalogReader {
+	device: HidDevice,
+	latest: AnalogReport,
+}
+
+impl AnalogReader {
+	/// Opens the pad with `serial`, or the first one found.
+	pub fn open(api: &HidApi, serial: Option<&str>) -> HidResult<Self> {
+		let info = api.device_list()
+			.filter(|d| is_analog(d))
+			.find(|d| serial.is_none_or(|serial| d.serial_number() == Some(serial)))
+			.ok_or_else(|| HidError::HidApiError { message: "No analog reports found".into() })?;
+		Ok(Self::new(info.open_device(api)?))
+	}
+
+	pub fn new(device: HidDevice) -> Self {
+		// The pad only sends on change, so start from what it sent last, a page per request
+		let mut latest = AnalogReport::new();
+		for _ in 0..ANALOG_PAGES {
+			let mut buf = [0; ANALOG_PAGE_LEN + 1];
+			buf[0] = ANALOG_REPORT_ID;
+			match device.get_input_report(&mut buf) {
+				Ok(len) => latest.apply_report(&buf[..len]),
+				// Keys start at rest then, until they move
+				Err(_) => break,
+			};
+		}
+		Self { device, latest }
+	}
+
+	/// Takes in the reports that arrived since the last call.
+	pub fn travel(&mut self) -> HidResult<&AnalogReport> {
+		let mut buf = [0; 64];
+		loop {
+			match self.device.read_timeout(&mut buf, 0)? {
+				0 => return Ok(&self.latest),
+				// The keyboard's reports come through here too
+				len => {
+					self.latest.apply_report(&buf[..len]);
+				}
+			}
+		}
+	}
+
+	/// Depth of `key` from 0.0 at rest to 1.0 bottomed out, as of the newest report.
+	pub fn read_analog(&mut self, key: usize) -> HidResult<f32> {
+		self.travel()?
+			.depth(key)
+			.ok_or_else(|| HidError::HidApiError { message: format!("The pad has no key {key}") })
+	}
+}
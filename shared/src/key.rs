@@ -0,0 +1,452 @@
+use crate::config::{full_scale, scale, Calibration, KeyConfig, KeyMode, Threshold, REFERENCE_BITS};
+use crate::filter::FilterState;
+
+/// Idle readings needed before the resting baseline is trusted.
+const SETTLE_SAMPLES: u16 = 1024;
+/// How far below the baseline a released key may read and still count as idle, at [`REFERENCE_BITS`].
+const IDLE_BAND: u16 = 64;
+/// The baseline moves by 1/2^DRIFT_SHIFT of the difference per idle reading.
+const DRIFT_SHIFT: u32 = 16;
+/// [`KeyState::travel`] of a key pressed all the way, in tenths of a percent.
+pub const FULL_TRAVEL: u16 = 1000;
+/// Narrowest learned travel taken for a press rather than noise at rest, at [`REFERENCE_BITS`].
+const MIN_SPAN: u16 = 256;
+
+// TODO convert to distance
+#[derive(Copy, Clone)]
+pub struct KeyState {
+	pub min: u16,
+	pub max: u16,
+	pub pressed: bool,
+	pub changed: bool,
+	/// Latest reading after filtering.
+	pub value: u16,
+	/// Reading where a rapid trigger key last turned around, the highest since its release
+	/// or the lowest since its press.
+	turn: u16,
+	/// Resolution of the readings, `config` and `calibration` are scaled to it.
+	bits: u8,
+	config: KeyConfig,
+	calibration: Calibration,
+	filter: FilterState,
+	drift: Drift,
+}
+
+/// Slow tracking of the resting reading, which wanders with temperature and supply voltage.
+#[derive(Copy, Clone)]
+struct Drift {
+	/// Counts up to `SETTLE_SAMPLES` before tracking starts.
+	idle: u16,
+	/// Resting reading everything was set up against.
+	reference: u16,
+	/// Tracked resting reading in 1/65536ths.
+	baseline: u32,
+	/// `baseline - reference`, limited to the configured maximum.
+	offset: i16,
+}
+
+/// Where a key's resting reading went since it was calibrated.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DriftReport {
+	pub baseline: u16,
+	/// Amount the thresholds are shifted by.
+	pub offset: i16,
+	/// The baseline moved further than the key's `max_drift`.
+	pub limited: bool,
+}
+
+impl KeyState {
+	/// `initial` should read as released, e.g. the ADC maximum.
+	pub const fn new(initial: u16) -> Self {
+		Self {
+			min: u16::MAX,
+			max: u16::MIN,
+			pressed: false,
+			changed: false,
+			value: initial,
+			turn: initial,
+			bits: REFERENCE_BITS,
+			config: KeyConfig::DEFAULT,
+			calibration: Calibration { rest: None, bottom: None },
+			filter: FilterState::new(initial),
+			drift: Drift {
+				idle: 0,
+				reference: initial,
+				baseline: (initial as u32) << DRIFT_SHIFT,
+				offset: 0,
+			},
+		}
+	}
+
+	/// Keeps what was learned while scanning, only a different filter or resolution starts over.
+	pub fn configure(&mut self, config: KeyConfig, calibration: Calibration, bits: u8) {
+		if bits != self.bits {
+			// Everything learned is in the old range
+			*self = Self::new(full_scale(bits));
+			self.bits = bits;
+		}
+		let config = config.scaled(bits);
+		let calibration = calibration.scaled(bits);
+
+		if config.filter != self.config.filter {
+			self.filter = FilterState::new(self.value);
+		}
+		if config.mode != self.config.mode {
+			self.turn = self.value;
+		}
+		if calibration.rest != self.calibration.rest {
+			// New calibration, drift is measured from scratch
+			self.drift.idle = 0;
+			self.drift.offset = 0;
+		}
+		self.config = config;
+		self.calibration = calibration;
+	}
+
+	pub fn drift(&self) -> DriftReport {
+		let baseline = (self.drift.baseline >> DRIFT_SHIFT) as u16;
+		DriftReport {
+			baseline,
+			offset: self.drift.offset,
+			limited: (baseline as i32 - self.drift.reference as i32).abs() > self.config.max_drift as i32,
+		}
+	}
+
+	pub fn update(&mut self, value: u16) {
+		// Readings drop as the magnet approaches, so rest is the maximum and bottom the minimum
+		self.max = self.calibration.rest.unwrap_or(self.max.max(value));
+		self.min = self.calibration.bottom.unwrap_or(self.min.min(value));
+
+		self.value = self.filter.update(self.config.filter, value);
+		self.track_drift();
+
+		let pressed = match self.config.mode {
+			KeyMode::Threshold(t) => {
+				let t = shift_threshold(t, self.drift.offset);
+				let value = self.apply_dead_zones(&t, self.value);
+				if self.pressed {
+					value <= t.release.max(t.actuation)
+				} else {
+					value < t.actuation
+				}
+			},
+			KeyMode::RapidTrigger(distance) => {
+				// Moving `distance` against the current direction flips the key, anywhere in the travel
+				if self.pressed {
+					self.turn = self.turn.min(self.value);
+					self.value - self.turn <= distance
+				} else {
+					// Rest caps the turn, so a filter settling from its seed value down to rest is no press
+					let rest = shift(self.max, self.drift.offset);
+					self.turn = self.turn.max(self.value).min(rest);
+					self.turn.saturating_sub(self.value) > distance
+				}
+			},
+		};
+
+		self.changed = pressed != self.pressed;
+		if self.changed {
+			self.turn = self.value;
+		}
+		self.pressed = pressed;
+	}
+
+	/// Follows the resting reading while the key is clearly idle.
+	///
+	/// Starts from the calibrated rest, or the learned maximum once the key has been idle for a while.
+	fn track_drift(&mut self) {
+		let drift = &mut self.drift;
+		if self.config.max_drift == 0 {
+			drift.offset = 0;
+			return;
+		}
+		if self.pressed {
+			return;
+		}
+
+		if drift.idle < SETTLE_SAMPLES {
+			drift.idle += 1;
+			if drift.idle == SETTLE_SAMPLES {
+				drift.reference = self.calibration.rest.unwrap_or(self.max);
+				drift.baseline = (self.value as u32) << DRIFT_SHIFT;
+			}
+			return;
+		}
+
+		let baseline = (drift.baseline >> DRIFT_SHIFT) as u16;
+		if self.value < baseline.saturating_sub(scale(IDLE_BAND, self.bits)) {
+			// On its way down, not resting
+			return;
+		}
+
+		let target = (self.value as i64) << DRIFT_SHIFT;
+		let current = drift.baseline as i64;
+		drift.baseline = (current + ((target - current) >> DRIFT_SHIFT)) as u32;
+
+		let max = self.config.max_drift as i32;
+		let offset = ((drift.baseline >> DRIFT_SHIFT) as i32 - drift.reference as i32).clamp(-max, max);
+		drift.offset = offset as i16;
+	}
+
+	/// How far the key is pressed, from 0 at rest to [`FULL_TRAVEL`] at the bottom.
+	///
+	/// 0 until both ends of the travel are known, an uncalibrated key has to be pressed once.
+	pub fn travel(&self) -> u16 {
+		let rest = shift(self.max, self.drift.offset) as u32;
+		let bottom = shift(self.min, self.drift.offset) as u32;
+		if self.max <= self.min || rest < bottom + scale(MIN_SPAN, self.bits) as u32 {
+			return 0;
+		}
+		let value = (self.value as u32).clamp(bottom, rest);
+		((rest - value) * FULL_TRAVEL as u32 / (rest - bottom)) as u16
+	}
+
+	/// Snaps readings near the ends of travel to the ends, once both ends are known.
+	fn apply_dead_zones(&self, t: &Threshold, value: u16) -> u16 {
+		if self.max <= self.min {
+			return value;
+		}
+		let max = shift(self.max, self.drift.offset);
+		let min = shift(self.min, self.drift.offset);
+		if value > max.saturating_sub(t.top_dead_zone) {
+			max
+		} else if value < min.saturating_add(t.bottom_dead_zone) {
+			min
+		} else {
+			value
+		}
+	}
+
+	// TODO adjust this (does not work correctly)
+	// fn pressed_percent(&self, value: u16) -> f64 {
+	//     let a = 1.0 / libm::cbrt(value as f64);
+	//     let b = 1.0 / libm::cbrt(self.max as f64);
+	//     let c = 1.0 / libm::cbrt(self.min as f64);
+	//
+	//     (a - b) / (c - b)
+	// }
+}
+
+fn shift(value: u16, offset: i16) -> u16 {
+	(value as i32 + offset as i32).clamp(0, u16::MAX as i32) as u16
+}
+
+fn shift_threshold(t: Threshold, offset: i16) -> Threshold {
+	Threshold {
+		actuation: shift(t.actuation, offset),
+		release: shift(t.release, offset),
+		..t
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::config::{full_scale, Calibration, KeyConfig, KeyMode, Threshold, REFERENCE_BITS};
+	use crate::filter::Filter;
+	use crate::key::{KeyState, FULL_TRAVEL, SETTLE_SAMPLES};
+
+	fn calibrated(threshold: Threshold) -> KeyState {
+		let mut key = KeyState::new(3000);
+		key.configure(
+			KeyConfig { mode: KeyMode::Threshold(threshold), filter: Filter::None, max_drift: 0 },
+			Calibration { rest: Some(3000), bottom: Some(1000) },
+			REFERENCE_BITS,
+		);
+		key
+	}
+
+	/// Counts state changes while the reading wobbles around the actuation point.
+	fn chatter(key: &mut KeyState) -> usize {
+		let mut changes = 0;
+		for value in [1490, 1510, 1495, 1520, 1480, 1530, 1490, 1550] {
+			key.update(value);
+			changes += key.changed as usize;
+		}
+		changes
+	}
+
+	#[test]
+	fn test_single_point_chatters() {
+		assert_eq!(chatter(&mut calibrated(Threshold::at(1500))), 8);
+	}
+
+	#[test]
+	fn test_hysteresis() {
+		let mut key = calibrated(Threshold { actuation: 1500, release: 1600, ..Threshold::at(0) });
+		assert_eq!(chatter(&mut key), 1);
+		assert!(key.pressed);
+
+		key.update(1601);
+		assert!(!key.pressed);
+	}
+
+	#[test]
+	fn test_dead_zones() {
+		// Actuation inside the top dead zone is never reached before the key leaves it
+		let mut key = calibrated(Threshold { top_dead_zone: 200, ..Threshold::at(2900) });
+		key.update(2850);
+		assert!(!key.pressed);
+		key.update(2750);
+		assert!(key.pressed);
+
+		// Release inside the bottom dead zone needs the key to leave it
+		let mut key = calibrated(Threshold { actuation: 1100, release: 1150, bottom_dead_zone: 200, ..Threshold::at(0) });
+		key.update(1150);
+		assert!(key.pressed);
+		key.update(1190);
+		assert!(key.pressed);
+		key.update(1210);
+		assert!(!key.pressed);
+	}
+
+	#[test]
+	fn test_rapid_trigger() {
+		let mut key = KeyState::new(3000);
+		key.configure(
+			KeyConfig { mode: KeyMode::RapidTrigger(100), filter: Filter::None, max_drift: 0 },
+			Calibration { rest: Some(3000), bottom: Some(1000) },
+			REFERENCE_BITS,
+		);
+
+		// Wobbles smaller than the distance change nothing
+		key.update(2950);
+		key.update(3000);
+		assert!(!key.pressed);
+
+		key.update(2850);
+		assert!(key.pressed && key.changed);
+		key.update(2000);
+		key.update(2080);
+		assert!(key.pressed && !key.changed);
+
+		// Lifting a little from the lowest point releases, going down again presses
+		key.update(2101);
+		assert!(!key.pressed && key.changed);
+		key.update(2150);
+		key.update(2049);
+		assert!(key.pressed);
+	}
+
+	#[test]
+	fn test_rapid_trigger_at_boot() {
+		let mut key = KeyState::new(full_scale(REFERENCE_BITS));
+		key.configure(
+			KeyConfig { mode: KeyMode::RapidTrigger(100), ..KeyConfig::DEFAULT },
+			Calibration::default(),
+			REFERENCE_BITS,
+		);
+
+		// The filter takes a while to come down from the seed value to the noisy rest
+		let mut presses = 0;
+		for i in 0..200 {
+			key.update(if i % 2 == 0 { 2995 } else { 3005 });
+			presses += (key.changed && key.pressed) as usize;
+		}
+		assert_eq!(presses, 0);
+
+		for _ in 0..16 {
+			key.update(2850);
+		}
+		assert!(key.pressed);
+	}
+
+	fn drifting(max_drift: u16) -> KeyState {
+		let mut key = KeyState::new(3000);
+		key.configure(
+			KeyConfig { mode: KeyMode::Threshold(Threshold::at(2800)), filter: Filter::None, max_drift },
+			Calibration::default(),
+			REFERENCE_BITS,
+		);
+		for _ in 0..SETTLE_SAMPLES {
+			key.update(3000);
+		}
+		key
+	}
+
+	/// Rest creeping down would actuate the key without compensation.
+	#[test]
+	fn test_follows_baseline() {
+		let mut key = drifting(300);
+		for rest in (2750..3000).rev().step_by(10) {
+			for _ in 0..50_000 {
+				key.update(rest);
+			}
+		}
+		assert!(!key.pressed);
+
+		let report = key.drift();
+		assert!((2740..2770).contains(&report.baseline), "{report:?}");
+		assert!(!report.limited);
+
+		// Still actuates relative to the new rest
+		key.update(2500);
+		assert!(key.pressed);
+	}
+
+	#[test]
+	fn test_limited() {
+		let mut key = drifting(100);
+		for rest in (2800..3000).rev().step_by(10) {
+			for _ in 0..100_000 {
+				key.update(rest);
+			}
+		}
+
+		let report = key.drift();
+		assert_eq!(report.offset, -100);
+		assert!(report.limited);
+	}
+
+	#[test]
+	fn test_ignores_presses() {
+		let mut key = drifting(300);
+		for _ in 0..100_000 {
+			key.update(1500);
+		}
+		assert_eq!(key.drift().offset, 0);
+	}
+
+	/// Thresholds are given at 12 bits and follow the readings to other resolutions.
+	#[test]
+	fn test_scaled_to_resolution() {
+		let mut key = KeyState::new(3000);
+		let config = KeyConfig { mode: KeyMode::Threshold(Threshold::at(1500)), filter: Filter::None, max_drift: 0 };
+		key.configure(config, Calibration::default(), 14);
+		key.update(4 * 1510);
+		assert!(!key.pressed);
+		key.update(4 * 1490);
+		assert!(key.pressed);
+
+		key.configure(config, Calibration::default(), 8);
+		key.update(1600 / 16);
+		assert!(!key.pressed);
+		key.update(1400 / 16);
+		assert!(key.pressed);
+	}
+
+	#[test]
+	fn test_travel() {
+		let mut key = calibrated(Threshold::at(1500));
+		key.update(3000);
+		assert_eq!(key.travel(), 0);
+		key.update(2000);
+		assert_eq!(key.travel(), FULL_TRAVEL / 2);
+		key.update(900);
+		assert_eq!(key.travel(), FULL_TRAVEL);
+
+		// Nothing to measure against before the key was pressed once
+		let mut key = KeyState::new(3000);
+		key.configure(KeyConfig { filter: Filter::None, ..KeyConfig::DEFAULT }, Calibration::default(), REFERENCE_BITS);
+		for i in 0..100 {
+			key.update(if i % 2 == 0 { 2995 } else { 3005 });
+			assert_eq!(key.travel(), 0);
+		}
+		key.update(2900);
+		assert_eq!(key.travel(), 0);
+
+		key.update(1005);
+		assert_eq!(key.travel(), FULL_TRAVEL);
+		key.update(2005);
+		assert_eq!(key.travel(), FULL_TRAVEL / 2);
+	}
+}
@@ -0,0 +1,56 @@
+//! Names of the HID keyboard and consumer page usages, spelled like keyberon's `KeyCode`.
+
+pub const NAMES: &[(u8, &str)] = &[
+	(0x04, "A"), (0x05, "B"), (0x06, "C"), (0x07, "D"), (0x08, "E"), (0x09, "F"),
+	(0x0A, "G"), (0x0B, "H"), (0x0C, "I"), (0x0D, "J"), (0x0E, "K"), (0x0F, "L"),
+	(0x10, "M"), (0x11, "N"), (0x12, "O"), (0x13, "P"), (0x14, "Q"), (0x15, "R"),
+	(0x16, "S"), (0x17, "T"), (0x18, "U"), (0x19, "V"), (0x1A, "W"), (0x1B, "X"),
+	(0x1C, "Y"), (0x1D, "Z"),
+	(0x1E, "Kb1"), (0x1F, "Kb2"), (0x20, "Kb3"), (0x21, "Kb4"), (0x22, "Kb5"),
+	(0x23, "Kb6"), (0x24, "Kb7"), (0x25, "Kb8"), (0x26, "Kb9"), (0x27, "Kb0"),
+	(0x28, "Enter"), (0x29, "Escape"), (0x2A, "BSpace"), (0x2B, "Tab"), (0x2C, "Space"),
+	(0x2D, "Minus"), (0x2E, "Equal"), (0x2F, "LBracket"), (0x30, "RBracket"), (0x31, "Bslash"),
+	(0x32, "NonUsHash"), (0x33, "SColon"), (0x34, "Quote"), (0x35, "Grave"), (0x36, "Comma"),
+	(0x37, "Dot"), (0x38, "Slash"), (0x39, "CapsLock"),
+	(0x3A, "F1"), (0x3B, "F2"), (0x3C, "F3"), (0x3D, "F4"), (0x3E, "F5"), (0x3F, "F6"),
+	(0x40, "F7"), (0x41, "F8"), (0x42, "F9"), (0x43, "F10"), (0x44, "F11"), (0x45, "F12"),
+	(0x46, "PScreen"), (0x47, "ScrollLock"), (0x48, "Pause"), (0x49, "Insert"), (0x4A, "Home"),
+	(0x4B, "PgUp"), (0x4C, "Delete"), (0x4D, "End"), (0x4E, "PgDown"),
+	(0x4F, "Right"), (0x50, "Left"), (0x51, "Down"), (0x52, "Up"), (0x53, "NumLock"),
+	(0x54, "KpSlash"), (0x55, "KpAsterisk"), (0x56, "KpMinus"), (0x57, "KpPlus"), (0x58, "KpEnter"),
+	(0x59, "Kp1"), (0x5A, "Kp2"), (0x5B, "Kp3"), (0x5C, "Kp4"), (0x5D, "Kp5"),
+	(0x5E, "Kp6"), (0x5F, "Kp7"), (0x60, "Kp8"), (0x61, "Kp9"), (0x62, "Kp0"),
+	(0x63, "KpDot"), (0x64, "NonUsBslash"), (0x65, "Application"),
+	(0xE0, "LCtrl"), (0xE1, "LShift"), (0xE2, "LAlt"), (0xE3, "LGui"),
+	(0xE4, "RCtrl"), (0xE5, "RShift"), (0xE6, "RAlt"), (0xE7, "RGui"),
+];
+
+/// First usage of the modifier range, these go into the modifier byte of a report.
+pub const MODIFIER_START: u8 = 0xE0;
+
+pub fn name(usage: u8) -> Option<&'static str> {
+	NAMES.iter().find(|(u, _)| *u == usage).map(|(_, n)| *n)
+}
+
+pub fn usage(name: &str) -> Option<u8> {
+	NAMES.iter().find(|(_, n)| *n == name).map(|(u, _)| *u)
+}
+
+/// Consumer page usages for media and browser keys.
+pub const CONSUMER_NAMES: &[(u16, &str)] = &[
+	(0x006F, "BrightnessUp"), (0x0070, "BrightnessDown"),
+	(0x00B5, "MediaNextSong"), (0x00B6, "MediaPreviousSong"), (0x00B7, "MediaStop"),
+	(0x00CD, "MediaPlayPause"), (0x00E2, "MediaMute"),
+	(0x00E9, "MediaVolUp"), (0x00EA, "MediaVolDown"),
+	(0x018A, "Mail"), (0x0192, "Calculator"), (0x0194, "MyComputer"),
+	(0x0221, "WwwSearch"), (0x0223, "WwwHome"), (0x0224, "WwwBack"), (0x0225, "WwwForward"),
+	(0x0226, "WwwStop"), (0x0227, "WwwRefresh"), (0x022A, "WwwFavorites"),
+];
+
+pub fn consumer_name(usage: u16) -> Option<&'static str> {
+	CONSUMER_NAMES.iter().find(|(u, _)| *u == usage).map(|(_, n)| *n)
+}
+
+pub fn consumer_usage(name: &str) -> Option<u16> {
+	CONSUMER_NAMES.iter().find(|(_, n)| *n == name).map(|(u, _)| *u)
+}
@@ -0,0 +1,283 @@
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+use musli::{Decode, Encode};
+use crate::keycode;
+use crate::mouse::MouseAction;
+use crate::report::CONSUMER_USAGE_MAX;
+
+/// Shape of the layout, keys are numbered row by row.
+///
+/// Fixed at build time for the firmware, the CLI and the analog plugin alike, so all three have to be
+/// rebuilt when a board changes it. Both refuse pads whose `DeviceInfo` reports another key count.
+pub const ROWS: usize = 1;
+pub const COLS: usize = 4;
+pub const KEY_COUNT: usize = ROWS * COLS;
+pub const LAYER_COUNT: usize = 4;
+pub const PROFILE_COUNT: usize = 4;
+pub const PROFILE_NAME_LEN: usize = 16;
+
+/// What a key does when pressed on a given layer.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+pub enum Action {
+	NoOp,
+	/// Fall through to the next lower layer.
+	Trans,
+	/// A usage from the HID keyboard page.
+	Key(u8),
+	/// Activate a layer while held.
+	Layer(u8),
+	/// A usage from the HID consumer page, like media and browser keys.
+	Consumer(u16),
+	/// Cursor movement, wheel or a button, analog speed while held.
+	Mouse(MouseAction),
+}
+
+impl Action {
+	/// Whether the pad can send this action, anything else is rejected before it reaches a keymap.
+	pub fn is_valid(&self) -> bool {
+		!matches!(self, Action::Consumer(usage) if *usage > CONSUMER_USAGE_MAX)
+	}
+}
+
+pub const fn key_index(row: usize, col: usize) -> usize {
+	row * COLS + col
+}
+
+pub type Keymap = [[Action; KEY_COUNT]; LAYER_COUNT];
+
+/// An empty keymap, transparent above the base layer.
+pub const EMPTY_KEYMAP: Keymap = {
+	let mut keymap = [[Action::Trans; KEY_COUNT]; LAYER_COUNT];
+	keymap[0] = [Action::NoOp; KEY_COUNT];
+	keymap
+};
+
+/// Written as `A`, `MediaVolUp`, `MouseUp`, `MouseButton(1)`, `NoOp`, `Trans` or `Layer(1)` in config files.
+impl Display for Action {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Action::NoOp => f.write_str("NoOp"),
+			Action::Trans => f.write_str("Trans"),
+			Action::Key(usage) => match keycode::name(*usage) {
+				Some(name) => f.write_str(name),
+				None => write!(f, "Key({usage:#04x})"),
+			},
+			Action::Layer(layer) => write!(f, "Layer({layer})"),
+			Action::Consumer(usage) => match keycode::consumer_name(*usage) {
+				Some(name) => f.write_str(name),
+				None => write!(f, "Consumer({usage:#06x})"),
+			},
+			Action::Mouse(MouseAction::Button(button)) => write!(f, "MouseButton({button})"),
+			Action::Mouse(action) => match MOUSE_NAMES.iter().find(|(a, _)| a == action) {
+				Some((_, name)) => f.write_str(name),
+				None => write!(f, "{action:?}"),
+			},
+		}
+	}
+}
+
+const MOUSE_NAMES: &[(MouseAction, &str)] = &[
+	(MouseAction::Up, "MouseUp"),
+	(MouseAction::Down, "MouseDown"),
+	(MouseAction::Left, "MouseLeft"),
+	(MouseAction::Right, "MouseRight"),
+	(MouseAction::WheelUp, "WheelUp"),
+	(MouseAction::WheelDown, "WheelDown"),
+	(MouseAction::WheelLeft, "WheelLeft"),
+	(MouseAction::WheelRight, "WheelRight"),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseActionError;
+
+impl FromStr for Action {
+	type Err = ParseActionError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let call = |name: &str| s
+			.strip_prefix(name)
+			.and_then(|rest| rest.strip_prefix('('))
+			.and_then(|rest| rest.strip_suffix(')'));
+		let number = |arg: &str| match arg.strip_prefix("0x") {
+			Some(hex) => u16::from_str_radix(hex, 16),
+			None => arg.parse(),
+		}.map_err(|_| ParseActionError);
+		let byte = |arg: &str| number(arg)?.try_into().map_err(|_| ParseActionError);
+
+		match s {
+			"NoOp" => Ok(Action::NoOp),
+			"Trans" => Ok(Action::Trans),
+			_ => if let Some(arg) = call("Layer") {
+				Ok(Action::Layer(byte(arg)?))
+			} else if let Some(arg) = call("Key") {
+				Ok(Action::Key(byte(arg)?))
+			} else if let Some(arg) = call("Consumer") {
+				Some(Action::Consumer(number(arg)?)).filter(Action::is_valid).ok_or(ParseActionError)
+			} else if let Some(arg) = call("MouseButton") {
+				Ok(Action::Mouse(MouseAction::Button(byte(arg)?)))
+			} else {
+				keycode::usage(s).map(Action::Key)
+					.or_else(|| keycode::consumer_usage(s).map(Action::Consumer))
+					.or_else(|| MOUSE_NAMES.iter().find(|(_, n)| *n == s).map(|(a, _)| Action::Mouse(*a)))
+					.ok_or(ParseActionError)
+			},
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl serde::Serialize for Action {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+#[cfg(feature = "std")]
+impl<'de> serde::Deserialize<'de> for Action {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+		s.parse().map_err(|_| serde::de::Error::custom(format_args!("unknown action `{s}`")))
+	}
+}
+
+/// Resolves key presses against a keymap into HID usages.
+///
+/// Takes the place of keyberon's `Layout`, which only works on `&'static` layers and so can't
+/// follow keymaps loaded from flash or changed over USB. The compiled in keymaps it replaced
+/// only held plain key codes, there was no hold-tap or other timed action to carry over.
+pub struct Layout {
+	/// Action each key resolved to when it was pressed, so a release matches its press across layer changes.
+	held: [Option<Action>; KEY_COUNT],
+}
+
+impl Layout {
+	pub const fn new() -> Self {
+		Self {
+			held: [None; KEY_COUNT],
+		}
+	}
+
+	pub fn event(&mut self, keymap: &Keymap, key: usize, pressed: bool) {
+		self.held[key] = if pressed {
+			Some(resolve(keymap, self.active_layer(), key))
+		} else {
+			None
+		};
+	}
+
+	/// Release everything, e.g. because the keymap was swapped.
+	pub fn clear(&mut self) {
+		self.held = [None; KEY_COUNT];
+	}
+
+	/// The highest layer of all held layer keys.
+	pub fn active_layer(&self) -> usize {
+		self.held.iter()
+			.filter_map(|a| match a {
+				Some(Action::Layer(l)) => Some(*l as usize),
+				_ => None,
+			})
+			.filter(|l| *l < LAYER_COUNT)
+			.max()
+			.unwrap_or(0)
+	}
+
+	pub fn keycodes(&self) -> impl Iterator<Item = u8> + '_ {
+		self.held.iter().filter_map(|a| match a {
+			Some(Action::Key(usage)) => Some(*usage),
+			_ => None,
+		})
+	}
+
+	/// Held mouse keys, with the key each is on.
+	pub fn mouse_actions(&self) -> impl Iterator<Item = (usize, MouseAction)> + '_ {
+		self.held.iter().enumerate().filter_map(|(key, a)| match a {
+			Some(Action::Mouse(action)) => Some((key, *action)),
+			_ => None,
+		})
+	}
+
+	pub fn consumer_usages(&self) -> impl Iterator<Item = u16> + '_ {
+		self.held.iter().filter_map(|a| match a {
+			Some(Action::Consumer(usage)) => Some(*usage),
+			_ => None,
+		})
+	}
+}
+
+impl Default for Layout {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+fn resolve(keymap: &Keymap, layer: usize, key: usize) -> Action {
+	keymap[..=layer].iter()
+		.rev()
+		.map(|l| l[key])
+		.find(|a| *a != Action::Trans)
+		.unwrap_or(Action::NoOp)
+}
+
+#[cfg(test)]
+mod test {
+	use std::string::ToString;
+	use std::vec::Vec;
+	use crate::keymap::{Action, Layout, ParseActionError, EMPTY_KEYMAP};
+	use crate::mouse::MouseAction;
+
+	#[test]
+	fn test_parse_roundtrip() {
+		let actions = [
+			Action::NoOp,
+			Action::Trans,
+			Action::Key(0x04),
+			Action::Key(0xA5),
+			Action::Layer(2),
+			Action::Consumer(0xE9),
+			Action::Consumer(0x0B0),
+			Action::Mouse(MouseAction::WheelLeft),
+			Action::Mouse(MouseAction::Button(2)),
+		];
+		for action in actions {
+			let s = action.to_string();
+			assert_eq!(s.parse(), Ok(action), "{s}");
+		}
+		assert_eq!("Kb1".parse(), Ok(Action::Key(0x1E)));
+		assert_eq!("MediaPlayPause".parse(), Ok(Action::Consumer(0xCD)));
+		assert_eq!("Key(0x100)".parse::<Action>(), Err(ParseActionError));
+		// Past what the consumer report descriptor declares
+		assert_eq!("Consumer(0x3ff)".parse(), Ok(Action::Consumer(0x3FF)));
+		assert_eq!("Consumer(0x400)".parse::<Action>(), Err(ParseActionError));
+	}
+
+	#[test]
+	fn test_layer_key() {
+		let mut keymap = EMPTY_KEYMAP;
+		keymap[0] = [Action::Layer(1), Action::Key(0x04), Action::Key(0x05), Action::NoOp];
+		keymap[1][1] = Action::Key(0x06);
+
+		let mut layout = Layout::new();
+		layout.event(&keymap, 0, true);
+		layout.event(&keymap, 1, true);
+		layout.event(&keymap, 2, true);
+		assert_eq!(layout.keycodes().collect::<Vec<_>>(), [0x06, 0x05]);
+
+		// Releasing the layer key keeps the already pressed key on its layer
+		layout.event(&keymap, 0, false);
+		layout.event(&keymap, 1, false);
+		assert_eq!(layout.keycodes().collect::<Vec<_>>(), [0x05]);
+	}
+
+	#[test]
+	fn test_consumer_key() {
+		let mut keymap = EMPTY_KEYMAP;
+		keymap[0] = [Action::Consumer(0xE9), Action::Key(0x04), Action::NoOp, Action::NoOp];
+
+		let mut layout = Layout::new();
+		layout.event(&keymap, 0, true);
+		layout.event(&keymap, 1, true);
+		assert_eq!(layout.keycodes().collect::<Vec<_>>(), [0x04]);
+		assert_eq!(layout.consumer_usages().collect::<Vec<_>>(), [0xE9]);
+	}
+}
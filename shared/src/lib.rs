@@ -1,6 +1,38 @@
 #![no_std]
 
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
+use musli::options::{self, ByteOrder, Integer, Options};
+use musli::wire::Encoding;
+
 pub mod message;
+pub mod keycode;
+pub mod keymap;
+pub mod config;
+pub mod filter;
+pub mod key;
+pub mod settings;
+pub mod store;
+pub mod report;
+pub mod supply;
+pub mod matrix;
+pub mod stats;
+pub mod curve;
+pub mod mouse;
+pub mod gamepad;
+pub mod midi;
+#[cfg(feature = "std")]
+pub mod file;
+#[cfg(feature = "host")]
+pub mod host;
 
 pub const VENDOR_ID: u16 = 0xc0de;
-pub const PRODUCT_ID: u16 = 0xcafe;
\ No newline at end of file
+pub const PRODUCT_ID: u16 = 0xcafe;
+
+const OPTIONS: Options = options::new()
+	.with_integer(Integer::Fixed)
+	.with_byte_order(ByteOrder::NETWORK)
+	.build();
+
+pub(crate) const ENCODING: Encoding<OPTIONS> = Encoding::new().with_options();
@@ -0,0 +1,76 @@
+//! Wiring of the sensors to layout positions, so the same firmware can drive boards of any shape.
+
+use crate::keymap::{key_index, COLS, KEY_COUNT, ROWS};
+
+/// Channels of one multiplexer.
+pub const MUX_POSITIONS: usize = 16;
+
+/// Where a key's sensor is wired.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Sensor {
+	pub mux: u8,
+	pub channel: u8,
+}
+
+/// Layout `(row, col)` of each multiplexer channel, `None` for channels without a sensor.
+pub type MuxMap<const MUXES: usize> = [[Option<(u8, u8)>; MUX_POSITIONS]; MUXES];
+
+/// Which sensor each key of the layout reads, `None` for positions no sensor is wired to.
+///
+/// Panics on positions outside the layout and positions wired twice, at compile time when used in a const.
+pub const fn sensors<const MUXES: usize>(map: &MuxMap<MUXES>) -> [Option<Sensor>; KEY_COUNT] {
+	let mut sensors = [None; KEY_COUNT];
+	let mut mux = 0;
+	while mux < MUXES {
+		let mut channel = 0;
+		while channel < MUX_POSITIONS {
+			if let Some((row, col)) = map[mux][channel] {
+				if row as usize >= ROWS || col as usize >= COLS {
+					panic!("sensor mapped outside of the layout");
+				}
+				let key = key_index(row as usize, col as usize);
+				if sensors[key].is_some() {
+					panic!("layout position mapped to two sensors");
+				}
+				sensors[key] = Some(Sensor { mux: mux as u8, channel: channel as u8 });
+			}
+			channel += 1;
+		}
+		mux += 1;
+	}
+	sensors
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_sensors() {
+		let mut map: MuxMap<2> = [[None; MUX_POSITIONS]; 2];
+		map[0][0] = Some((0, 1));
+		map[1][15] = Some((0, 0));
+
+		let sensors = sensors(&map);
+		assert_eq!(sensors[key_index(0, 0)], Some(Sensor { mux: 1, channel: 15 }));
+		assert_eq!(sensors[key_index(0, 1)], Some(Sensor { mux: 0, channel: 0 }));
+		assert_eq!(sensors.iter().flatten().count(), 2);
+	}
+
+	#[test]
+	#[should_panic]
+	fn test_mapped_twice() {
+		let mut map: MuxMap<1> = [[None; MUX_POSITIONS]];
+		map[0][0] = Some((0, 0));
+		map[0][1] = Some((0, 0));
+		sensors(&map);
+	}
+
+	#[test]
+	#[should_panic]
+	fn test_outside_layout() {
+		let mut map: MuxMap<1> = [[None; MUX_POSITIONS]];
+		map[0][0] = Some((ROWS as u8, 0));
+		sensors(&map);
+	}
+}
@@ -1,16 +1,15 @@
 use core::sync::atomic::{AtomicU32};
-use musli::{Decode, Encode, FixedBytes, Options, options};
-use musli::options::{ByteOrder, Integer};
-use musli::wire::Encoding;
-
-const OPTIONS: Options = options::new()
-	.with_integer(Integer::Fixed)
-	.with_byte_order(ByteOrder::NETWORK)
-	.build();
-
-const ENCODING: Encoding<OPTIONS> = Encoding::new().with_options();
+use musli::{Decode, Encode, FixedBytes};
+use crate::config::{Calibration, KeyConfig, ScanConfig, UsbConfig};
+use crate::gamepad::GamepadBinding;
+use crate::midi::MidiConfig;
+use crate::keymap::{Action, PROFILE_NAME_LEN};
+use crate::mouse::MouseConfig;
+use crate::ENCODING;
 
 pub const MESSAGE_BUF_SIZE: usize = 64;
+/// Bytes of a settings image carried by one message.
+pub const IMAGE_CHUNK_LEN: usize = 32;
 
 static NEXT_MSG_ID: AtomicU32 = AtomicU32::new(0);
 
@@ -19,6 +18,225 @@ pub enum Message {
 	Ping,
 	Pong,
 	ToggleDebugLed,
+	/// Ask the device to start pushing messages of a stream to the host.
+	Subscribe(Stream),
+	Unsubscribe(Stream),
+	/// Pushed to subscribers of [`Stream::KeyEvents`] whenever a key changes state.
+	KeyEvent {
+		key: u8,
+		pressed: bool,
+	},
+	/// Switch to another profile, answered with [`Message::ActiveProfile`].
+	///
+	/// Unknown profiles leave the active one untouched.
+	SetProfile(u8),
+	GetProfile,
+	ActiveProfile(u8),
+	GetDeviceInfo,
+	DeviceInfo {
+		keys: u8,
+		layers: u8,
+		profiles: u8,
+		/// Effective resolution of the readings, thresholds are scaled to it.
+		bits: u8,
+		/// Conversions per reading.
+		samples: u8,
+	},
+	// Getters are answered with the matching data message, setters with `Ack` or `Error`.
+	// Changes only apply to the running device until `SaveSettings`.
+	GetAction {
+		profile: u8,
+		layer: u8,
+		key: u8,
+	},
+	SetAction {
+		profile: u8,
+		layer: u8,
+		key: u8,
+		action: Action,
+	},
+	Action {
+		profile: u8,
+		layer: u8,
+		key: u8,
+		action: Action,
+	},
+	GetKeyConfig(u8),
+	SetKeyConfig {
+		key: u8,
+		config: KeyConfig,
+	},
+	KeyConfig {
+		key: u8,
+		config: KeyConfig,
+	},
+	GetCalibration(u8),
+	SetCalibration {
+		key: u8,
+		calibration: Calibration,
+	},
+	Calibration {
+		key: u8,
+		calibration: Calibration,
+	},
+	GetDrift(u8),
+	/// How far a key's resting reading moved since calibration, see `shared::key::DriftReport`.
+	Drift {
+		key: u8,
+		baseline: u16,
+		offset: i16,
+		limited: bool,
+	},
+	GetSupply,
+	/// Analog supply and die temperature, the latter in hundredths of a degree.
+	Supply {
+		vdda_mv: u16,
+		temperature: i16,
+	},
+	GetScanConfig,
+	SetScanConfig(ScanConfig),
+	ScanConfig(ScanConfig),
+	GetUsbConfig,
+	/// Takes effect once saved, the pad then drops off the bus and enumerates again.
+	SetUsbConfig(UsbConfig),
+	UsbConfig(UsbConfig),
+	GetMouseConfig,
+	SetMouseConfig(MouseConfig),
+	MouseConfig(MouseConfig),
+	GetGamepadBinding {
+		profile: u8,
+		key: u8,
+	},
+	/// `None` leaves the key off the gamepad.
+	SetGamepadBinding {
+		profile: u8,
+		key: u8,
+		binding: Option<GamepadBinding>,
+	},
+	GamepadBinding {
+		profile: u8,
+		key: u8,
+		binding: Option<GamepadBinding>,
+	},
+	GetMidiConfig(u8),
+	SetMidiConfig {
+		profile: u8,
+		config: MidiConfig,
+	},
+	MidiConfig {
+		profile: u8,
+		config: MidiConfig,
+	},
+	GetMidiNote {
+		profile: u8,
+		key: u8,
+	},
+	/// `None` keeps the key silent, notes go up to 127.
+	SetMidiNote {
+		profile: u8,
+		key: u8,
+		note: Option<u8>,
+	},
+	MidiNote {
+		profile: u8,
+		key: u8,
+		note: Option<u8>,
+	},
+	/// Act as if the key was pressed or released, answered with `Ack`.
+	///
+	/// Goes through the keymap like a real key, for measuring the latency of reports from the host.
+	SimulateKey {
+		key: u8,
+		pressed: bool,
+	},
+	/// Count full scans of the matrix for a second, answered with `ScanRate`.
+	Benchmark,
+	/// Every key is read once per scan.
+	ScanRate {
+		scans_per_second: u32,
+	},
+	/// Timing of the scan loop since the last reset, in microseconds, answered with `ScanStats`.
+	GetScanStats,
+	ResetScanStats,
+	ScanStats {
+		scans: u32,
+		/// Ticks skipped because a scan ran into the next one.
+		missed: u32,
+		min: u32,
+		avg: u32,
+		max: u32,
+		/// How late scans start after their tick.
+		avg_jitter: u32,
+		max_jitter: u32,
+		/// From a tick being due until the reports were queued for USB, at worst.
+		max_delay: u32,
+	},
+	GetProfileName(u8),
+	SetProfileName {
+		profile: u8,
+		#[musli(bytes)]
+		name: [u8; PROFILE_NAME_LEN],
+	},
+	ProfileName {
+		profile: u8,
+		#[musli(bytes)]
+		name: [u8; PROFILE_NAME_LEN],
+	},
+	/// Persist the running settings to flash.
+	SaveSettings,
+	// Backup and restore move the payload of a `shared::store` image in chunks,
+	// the header fields travel in `BeginBackup`'s answer and `BeginRestore`.
+	/// Snapshot the complete settings for reading, answered with `ImageInfo`.
+	BeginBackup,
+	ImageInfo {
+		version: u16,
+		len: u16,
+		crc: u32,
+	},
+	/// Read `IMAGE_CHUNK_LEN` bytes of the snapshot, answered with `ImageChunk`.
+	ReadImage(u16),
+	/// Start replacing the complete settings, nothing changes until `CommitRestore`.
+	BeginRestore {
+		version: u16,
+		len: u16,
+		crc: u32,
+	},
+	/// Answered with `Ack` when sent to the device.
+	ImageChunk {
+		offset: u16,
+		len: u8,
+		#[musli(bytes)]
+		data: [u8; IMAGE_CHUNK_LEN],
+	},
+	/// Check, persist and apply the restored image. If any of that fails the previous settings stay.
+	CommitRestore,
+	/// Wipe the stored settings and go back to the defaults compiled into the firmware.
+	FactoryReset,
+	Ack,
+	Error(ErrorCode),
+}
+
+/// Unsolicited data the device can push to the host.
+///
+/// Subscriptions are not remembered across a reconnect, the host has to subscribe again.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+pub enum Stream {
+	KeyEvents,
+}
+
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+pub enum ErrorCode {
+	/// A key, layer or profile index past what the device has.
+	OutOfRange,
+	Flash,
+	/// Image chunks without a matching `BeginBackup` or `BeginRestore`.
+	NoTransfer,
+	/// A restored image that does not check out.
+	InvalidImage,
+	/// The request could not be queued, try again later.
+	Busy,
+	/// An action the pad can't send, like a consumer usage past what its descriptor declares.
+	InvalidAction,
 }
 
 impl Message {
@@ -35,14 +253,40 @@ impl Message {
 
 #[cfg(test)]
 mod test {
-	use crate::message::Message;
+	use crate::keymap::Action;
+	use crate::message::{Message, IMAGE_CHUNK_LEN};
+	use crate::settings::encode_name;
 
 	#[test]
 	fn test_simple() {
-		let msg = Message::request_heartbeat(42);
+		let msg = Message::SetAction { profile: 3, layer: 1, key: 2, action: Action::Layer(1) };
 		let ser = msg.serialize();
 
 		let dec = Message::deserialize(ser.as_slice());
 		assert_eq!(dec, msg)
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn test_fits_buffer() {
+		let msg = Message::ProfileName { profile: 255, name: encode_name("0123456789abcdef") };
+		let dec = Message::deserialize(msg.serialize().as_slice());
+		assert_eq!(dec, msg);
+
+		let msg = Message::ImageChunk { offset: u16::MAX, len: 32, data: [0xFF; IMAGE_CHUNK_LEN] };
+		let dec = Message::deserialize(msg.serialize().as_slice());
+		assert_eq!(dec, msg);
+
+		let msg = Message::ScanStats {
+			scans: u32::MAX,
+			missed: u32::MAX,
+			min: u32::MAX,
+			avg: u32::MAX,
+			max: u32::MAX,
+			avg_jitter: u32::MAX,
+			max_jitter: u32::MAX,
+			max_delay: u32::MAX,
+		};
+		let dec = Message::deserialize(msg.serialize().as_slice());
+		assert_eq!(dec, msg)
+	}
+}
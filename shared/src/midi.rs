@@ -0,0 +1,222 @@
+//! Keys as MIDI notes, with the velocity following how fast a key was pressed.
+
+use musli::{Decode, Encode};
+use crate::curve::Curve;
+use crate::key::FULL_TRAVEL;
+use crate::keymap::KEY_COUNT;
+
+/// Travel a key has to leave behind before its press is timed, so resting noise doesn't start it.
+const START_TRAVEL: u16 = FULL_TRAVEL / 20;
+const MIDI_MAX: u32 = 127;
+
+/// Settings of a profile shared by all of its notes.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct MidiConfig {
+	/// 0 to 15, most software shows them as channels 1 to 16.
+	pub channel: u8,
+	/// Time from leaving the top to actuating that gives the full velocity, in microseconds.
+	pub fastest_us: u32,
+	/// Time from leaving the top to actuating that gives the lowest velocity.
+	pub slowest_us: u32,
+	/// How velocity follows the speed of a press.
+	pub velocity: Curve,
+	/// Send polyphonic pressure following the key's travel while a note is held.
+	pub aftertouch: bool,
+}
+
+impl MidiConfig {
+	pub const DEFAULT: Self = Self {
+		channel: 0,
+		fastest_us: 3_000,
+		slowest_us: 60_000,
+		velocity: Curve::Linear,
+		aftertouch: false,
+	};
+
+	/// Velocity for a press that took `elapsed_us` from the top to actuating, 1 to 127.
+	pub fn velocity(&self, elapsed_us: u32) -> u8 {
+		let window = self.slowest_us.saturating_sub(self.fastest_us).max(1);
+		let late = elapsed_us.saturating_sub(self.fastest_us).min(window);
+		let speed = (window - late) as u64 * FULL_TRAVEL as u64 / window as u64;
+		let velocity = self.velocity.apply(speed as u16) as u32 * MIDI_MAX / FULL_TRAVEL as u32;
+		velocity.clamp(1, MIDI_MAX) as u8
+	}
+}
+
+impl Default for MidiConfig {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MidiEvent {
+	NoteOn { channel: u8, note: u8, velocity: u8 },
+	NoteOff { channel: u8, note: u8 },
+	PolyPressure { channel: u8, note: u8, pressure: u8 },
+}
+
+impl MidiEvent {
+	/// USB MIDI event packet on cable 0: code index, status and two data bytes.
+	pub fn to_packet(&self) -> [u8; 4] {
+		let (status, data1, data2) = match *self {
+			MidiEvent::NoteOn { channel, note, velocity } => (0x90 | channel, note, velocity),
+			// Release velocity isn't measured, 64 is what keyboards without it send
+			MidiEvent::NoteOff { channel, note } => (0x80 | channel, note, 64),
+			MidiEvent::PolyPressure { channel, note, pressure } => (0xA0 | channel, note, pressure),
+		};
+		[status >> 4, status, data1 & 0x7f, data2 & 0x7f]
+	}
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Voice {
+	/// When the key left the top of its travel.
+	started_us: Option<u32>,
+	/// Channel and note sounding, so the release matches the press across setting changes.
+	playing: Option<(u8, u8)>,
+	pressure: u8,
+}
+
+/// Turns key presses into notes.
+pub struct MidiKeys {
+	voices: [Voice; KEY_COUNT],
+}
+
+impl MidiKeys {
+	pub const fn new() -> Self {
+		Self {
+			voices: [Voice { started_us: None, playing: None, pressure: 0 }; KEY_COUNT],
+		}
+	}
+
+	/// Follows every key, with its travel and whether it is pressed, `now_us` from a free running clock.
+	///
+	/// Keys without a note stay silent.
+	pub fn update(
+		&mut self,
+		config: &MidiConfig,
+		notes: &[Option<u8>; KEY_COUNT],
+		keys: impl Iterator<Item = (u16, bool)>,
+		now_us: u32,
+		mut send: impl FnMut(MidiEvent),
+	) {
+		let channel = config.channel & 0x0f;
+		for ((voice, note), (travel, pressed)) in self.voices.iter_mut().zip(notes).zip(keys) {
+			if travel <= START_TRAVEL {
+				voice.started_us = None;
+			} else if voice.started_us.is_none() {
+				voice.started_us = Some(now_us);
+			}
+
+			match (voice.playing, pressed, note) {
+				(None, true, Some(note)) => {
+					let elapsed = now_us.wrapping_sub(voice.started_us.unwrap_or(now_us));
+					send(MidiEvent::NoteOn { channel, note: *note, velocity: config.velocity(elapsed) });
+					voice.playing = Some((channel, *note));
+					voice.pressure = 0;
+				}
+				(Some((channel, note)), false, _) => {
+					send(MidiEvent::NoteOff { channel, note });
+					voice.playing = None;
+					// Rapid trigger presses again without going back up, time those from here
+					voice.started_us = None;
+				}
+				(Some((channel, note)), true, _) if config.aftertouch => {
+					let pressure = (travel.min(FULL_TRAVEL) as u32 * MIDI_MAX / FULL_TRAVEL as u32) as u8;
+					if pressure != voice.pressure {
+						send(MidiEvent::PolyPressure { channel, note, pressure });
+						voice.pressure = pressure;
+					}
+				}
+				_ => {}
+			}
+		}
+	}
+
+	/// Ends every note, e.g. because the profile changed.
+	pub fn release_all(&mut self, mut send: impl FnMut(MidiEvent)) {
+		for voice in &mut self.voices {
+			if let Some((channel, note)) = voice.playing.take() {
+				send(MidiEvent::NoteOff { channel, note });
+			}
+			voice.started_us = None;
+		}
+	}
+}
+
+impl Default for MidiKeys {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::vec::Vec;
+	use crate::curve::Curve;
+	use crate::key::FULL_TRAVEL;
+	use crate::keymap::KEY_COUNT;
+	use crate::midi::{MidiConfig, MidiEvent, MidiKeys};
+
+	fn notes() -> [Option<u8>; KEY_COUNT] {
+		let mut notes = [None; KEY_COUNT];
+		notes[0] = Some(36);
+		notes
+	}
+
+	/// Key 0 at `travel`, the others at rest.
+	fn step(midi: &mut MidiKeys, config: &MidiConfig, travel: u16, pressed: bool, now_us: u32) -> Vec<MidiEvent> {
+		let mut events = Vec::new();
+		let keys = (0..KEY_COUNT).map(|k| if k == 0 { (travel, pressed) } else { (0, false) });
+		midi.update(config, &notes(), keys, now_us, |e| events.push(e));
+		events
+	}
+
+	#[test]
+	fn test_velocity() {
+		let config = MidiConfig::DEFAULT;
+		assert_eq!(config.velocity(0), 127);
+		assert_eq!(config.velocity(config.fastest_us), 127);
+		assert_eq!(config.velocity(config.slowest_us), 1);
+		assert_eq!(config.velocity(u32::MAX), 1);
+
+		let middle = (config.fastest_us + config.slowest_us) / 2;
+		assert_eq!(config.velocity(middle), 63);
+		let quadratic = MidiConfig { velocity: Curve::Quadratic, ..config };
+		assert!(quadratic.velocity(middle) < config.velocity(middle));
+	}
+
+	#[test]
+	fn test_notes() {
+		let config = MidiConfig { channel: 9, aftertouch: true, ..MidiConfig::DEFAULT };
+		let mut midi = MidiKeys::new();
+
+		assert_eq!(step(&mut midi, &config, FULL_TRAVEL / 2, false, 1_000), []);
+		let slow = config.slowest_us;
+		assert_eq!(step(&mut midi, &config, FULL_TRAVEL / 2, true, 1_000 + slow), [
+			MidiEvent::NoteOn { channel: 9, note: 36, velocity: 1 },
+		]);
+		assert_eq!(step(&mut midi, &config, FULL_TRAVEL, true, 2_000 + slow), [
+			MidiEvent::PolyPressure { channel: 9, note: 36, pressure: 127 },
+		]);
+		assert_eq!(step(&mut midi, &config, FULL_TRAVEL, true, 3_000 + slow), []);
+		assert_eq!(step(&mut midi, &config, 0, false, 4_000 + slow), [MidiEvent::NoteOff { channel: 9, note: 36 }]);
+
+		// Straight from rest to actuated within one scan
+		assert_eq!(step(&mut midi, &config, FULL_TRAVEL, true, 5_000 + slow), [
+			MidiEvent::NoteOn { channel: 9, note: 36, velocity: 127 },
+		]);
+		let mut released = Vec::new();
+		midi.release_all(|e| released.push(e));
+		assert_eq!(released, [MidiEvent::NoteOff { channel: 9, note: 36 }]);
+	}
+
+	#[test]
+	fn test_packet() {
+		assert_eq!(MidiEvent::NoteOn { channel: 9, note: 36, velocity: 100 }.to_packet(), [0x09, 0x99, 36, 100]);
+		assert_eq!(MidiEvent::NoteOff { channel: 0, note: 60 }.to_packet(), [0x08, 0x80, 60, 64]);
+		assert_eq!(MidiEvent::PolyPressure { channel: 1, note: 60, pressure: 5 }.to_packet(), [0x0A, 0xA1, 60, 5]);
+	}
+}
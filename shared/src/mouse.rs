@@ -0,0 +1,173 @@
+//! Mouse keys: cursor, wheel and buttons, with the speed following how far a key is pressed.
+
+use musli::{Decode, Encode};
+use crate::curve::{Curve, Response};
+use crate::key::FULL_TRAVEL;
+use crate::report::MouseReport;
+
+/// What a mouse key does while held.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+pub enum MouseAction {
+	Up,
+	Down,
+	Left,
+	Right,
+	WheelUp,
+	WheelDown,
+	WheelLeft,
+	WheelRight,
+	/// Buttons 1 to 5, 1 being the primary one.
+	Button(u8),
+}
+
+/// Shared by all mouse keys.
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone, Copy)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct MouseConfig {
+	/// Cursor speed with a movement key pressed all the way, in counts per second.
+	pub speed: u16,
+	/// Wheel speed with a wheel key pressed all the way, in detents per second.
+	pub wheel_speed: u16,
+	/// How the speed follows the key's travel.
+	pub response: Response,
+}
+
+impl MouseConfig {
+	pub const DEFAULT: Self = Self {
+		speed: 1500,
+		wheel_speed: 20,
+		response: Response { curve: Curve::Quadratic, ..Response::DEFAULT },
+	};
+}
+
+impl Default for MouseConfig {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+/// Movement is accumulated in millionths of a count, so slow speeds still move at fast scan rates.
+const SUBSTEPS: i64 = 1_000_000;
+
+/// Turns held mouse keys into reports.
+pub struct MouseKeys {
+	x: i64,
+	y: i64,
+	wheel: i64,
+	pan: i64,
+	buttons: u8,
+	/// Buttons in the last report.
+	reported: u8,
+}
+
+impl MouseKeys {
+	pub const fn new() -> Self {
+		Self {
+			x: 0,
+			y: 0,
+			wheel: 0,
+			pan: 0,
+			buttons: 0,
+			reported: 0,
+		}
+	}
+
+	/// Moves for `elapsed_us` with the held mouse keys, each with how far it is pressed.
+	pub fn update(&mut self, config: &MouseConfig, held: impl Iterator<Item = (MouseAction, u16)>, elapsed_us: u32) {
+		let mut buttons = 0;
+		let mut moving = false;
+		for (action, travel) in held {
+			let speed = config.response.apply(travel) as i64;
+			let moved = |per_second: u16| per_second as i64 * speed * elapsed_us as i64 / FULL_TRAVEL as i64;
+			match action {
+				MouseAction::Up => self.y -= moved(config.speed),
+				MouseAction::Down => self.y += moved(config.speed),
+				MouseAction::Left => self.x -= moved(config.speed),
+				MouseAction::Right => self.x += moved(config.speed),
+				MouseAction::WheelUp => self.wheel += moved(config.wheel_speed),
+				MouseAction::WheelDown => self.wheel -= moved(config.wheel_speed),
+				MouseAction::WheelLeft => self.pan -= moved(config.wheel_speed),
+				MouseAction::WheelRight => self.pan += moved(config.wheel_speed),
+				MouseAction::Button(b @ 1..=5) => buttons |= 1 << (b - 1),
+				MouseAction::Button(_) => {}
+			}
+			moving |= !matches!(action, MouseAction::Button(_));
+		}
+		self.buttons = buttons;
+		if !moving {
+			// Leftover fractions would move a little on the next press
+			(self.x, self.y, self.wheel, self.pan) = (0, 0, 0, 0);
+		}
+	}
+
+	/// Whole counts moved since the last report, `None` if there is nothing new to tell the host.
+	pub fn report(&mut self) -> Option<MouseReport> {
+		let report = MouseReport {
+			buttons: self.buttons,
+			x: take(&mut self.x),
+			y: take(&mut self.y),
+			wheel: take(&mut self.wheel),
+			pan: take(&mut self.pan),
+		};
+		let moved = (report.x, report.y, report.wheel, report.pan) != (0, 0, 0, 0);
+		if !moved && report.buttons == self.reported {
+			return None;
+		}
+		self.reported = report.buttons;
+		Some(report)
+	}
+}
+
+impl Default for MouseKeys {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Takes the whole counts that fit a report out of `acc`.
+fn take(acc: &mut i64) -> i8 {
+	let whole = (*acc / SUBSTEPS).clamp(-127, 127);
+	*acc -= whole * SUBSTEPS;
+	whole as i8
+}
+
+#[cfg(test)]
+mod test {
+	use core::iter;
+	use crate::curve::{Curve, Response};
+	use crate::key::FULL_TRAVEL;
+	use crate::mouse::{MouseAction, MouseConfig, MouseKeys};
+
+	const LINEAR: MouseConfig = MouseConfig {
+		speed: 1000,
+		wheel_speed: 10,
+		response: Response { curve: Curve::Linear, ..Response::DEFAULT },
+	};
+
+	#[test]
+	fn test_speed_follows_travel() {
+		let mut mouse = MouseKeys::new();
+		// A millisecond at full and at half travel
+		mouse.update(&LINEAR, iter::once((MouseAction::Right, FULL_TRAVEL)), 1000);
+		assert_eq!(mouse.report().map(|r| r.x), Some(1));
+		mouse.update(&LINEAR, iter::once((MouseAction::Right, FULL_TRAVEL / 2)), 1000);
+		assert_eq!(mouse.report(), None);
+		mouse.update(&LINEAR, iter::once((MouseAction::Right, FULL_TRAVEL / 2)), 1000);
+		assert_eq!(mouse.report().map(|r| r.x), Some(1));
+	}
+
+	#[test]
+	fn test_buttons_and_wheel() {
+		let mut mouse = MouseKeys::new();
+		let held = [(MouseAction::Button(1), 300), (MouseAction::WheelDown, FULL_TRAVEL)];
+		mouse.update(&LINEAR, held.into_iter(), 100_000);
+		let report = mouse.report().unwrap();
+		assert_eq!((report.buttons, report.wheel), (1, -1));
+
+		// Unchanged buttons without movement need no report
+		mouse.update(&LINEAR, iter::once((MouseAction::Button(1), 300)), 1000);
+		assert_eq!(mouse.report(), None);
+		mouse.update(&LINEAR, iter::empty(), 1000);
+		assert_eq!(mouse.report().map(|r| r.buttons), Some(0));
+	}
+}
@@ -0,0 +1,651 @@
+use crate::config::Rollover;
+use crate::key::FULL_TRAVEL;
+use crate::keycode::MODIFIER_START;
+use crate::keymap::KEY_COUNT;
+
+/// Report IDs on the pad's HID interface, which carries every report so the endpoints go further.
+///
+/// Hosts in the boot protocol only get the keyboard's [`BootReport`], without an ID.
+pub const KEYBOARD_REPORT_ID: u8 = 1;
+pub const CONSUMER_REPORT_ID: u8 = 2;
+pub const MOUSE_REPORT_ID: u8 = 3;
+pub const GAMEPAD_REPORT_ID: u8 = 4;
+pub const ANALOG_REPORT_ID: u8 = 5;
+
+/// Room for [`report_descriptor`], more than it needs.
+pub const REPORT_DESCRIPTOR_MAX_LEN: usize = 512;
+
+/// Every report of the HID interface, each under its ID, with the keyboard in the format `rollover` picks.
+pub fn report_descriptor(rollover: Rollover, buf: &mut [u8; REPORT_DESCRIPTOR_MAX_LEN]) -> &[u8] {
+	let keyboard = match rollover {
+		Rollover::SixKey => BOOT_DESCRIPTOR,
+		Rollover::NKey => NKRO_DESCRIPTOR,
+	};
+	let mut len = 0;
+	for part in [keyboard, CONSUMER_DESCRIPTOR, MOUSE_DESCRIPTOR, GAMEPAD_DESCRIPTOR, ANALOG_DESCRIPTOR] {
+		buf[len..len + part.len()].copy_from_slice(part);
+		len += part.len();
+	}
+	&buf[..len]
+}
+
+/// Boot protocol keyboard report: modifier bits, a reserved byte and up to six keys.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct BootReport([u8; 8]);
+
+impl BootReport {
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.0
+	}
+
+	pub fn press(&mut self, usage: u8) {
+		if usage >= MODIFIER_START {
+			self.0[0] |= 1 << (usage - MODIFIER_START);
+			return;
+		}
+
+		let keys = &mut self.0[2..];
+		if keys.contains(&usage) {
+			return;
+		}
+		match keys.iter_mut().find(|k| **k == 0) {
+			Some(slot) => *slot = usage,
+			// Too many keys, report ErrorRollOver in every slot
+			None => keys.fill(0x01),
+		}
+	}
+}
+
+impl FromIterator<u8> for BootReport {
+	fn from_iter<T: IntoIterator<Item = u8>>(iter: T) -> Self {
+		let mut report = Self::default();
+		for usage in iter {
+			report.press(usage);
+		}
+		report
+	}
+}
+
+/// Length of [`NkroReport`]: the modifier bits, then one bit for every usage below the modifiers.
+pub const NKRO_REPORT_LEN: usize = 1 + MODIFIER_START as usize / 8;
+
+/// N-key rollover report, every key pressed is in it no matter how many there are.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct NkroReport([u8; NKRO_REPORT_LEN]);
+
+impl NkroReport {
+	pub const fn new() -> Self {
+		Self([0; NKRO_REPORT_LEN])
+	}
+
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.0
+	}
+
+	pub fn press(&mut self, usage: u8) {
+		if usage >= MODIFIER_START {
+			self.0[0] |= 1 << (usage - MODIFIER_START);
+		} else {
+			self.0[1 + usage as usize / 8] |= 1 << (usage % 8);
+		}
+	}
+
+	/// Pressed usages, keys from the lowest up followed by the modifiers.
+	pub fn usages(&self) -> impl Iterator<Item = u8> + '_ {
+		let keys = (0..MODIFIER_START).filter(|u| self.0[1 + *u as usize / 8] & (1 << (u % 8)) != 0);
+		let modifiers = (0..8).filter(|bit| self.0[0] & (1 << bit) != 0).map(|bit| MODIFIER_START + bit);
+		keys.chain(modifiers)
+	}
+
+	/// The same keys for hosts limited to six, in the order of their usages.
+	pub fn to_boot(&self) -> BootReport {
+		self.usages().collect()
+	}
+
+	/// The bytes to send in `format`, written to `buf`.
+	pub fn encode<'a>(&self, format: ReportFormat, buf: &'a mut [u8; NKRO_REPORT_LEN]) -> &'a [u8] {
+		let boot;
+		let bytes = match format {
+			ReportFormat::Boot => {
+				boot = self.to_boot();
+				boot.as_bytes()
+			}
+			ReportFormat::Nkro => self.as_bytes(),
+		};
+		buf[..bytes.len()].copy_from_slice(bytes);
+		&buf[..bytes.len()]
+	}
+}
+
+/// Layout of the keyboard's input report.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ReportFormat {
+	/// [`BootReport`], also what the six key report descriptor describes.
+	Boot,
+	/// [`NkroReport`].
+	Nkro,
+}
+
+impl ReportFormat {
+	/// What the host expects, given the setting the descriptor was built from and the protocol the host picked.
+	///
+	/// Hosts in the boot protocol, like BIOS setup, don't read the descriptor and always get the boot report.
+	pub fn negotiated(rollover: Rollover, boot_protocol: bool) -> Self {
+		match rollover {
+			Rollover::NKey if !boot_protocol => ReportFormat::Nkro,
+			_ => ReportFormat::Boot,
+		}
+	}
+}
+
+impl Default for NkroReport {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl FromIterator<u8> for NkroReport {
+	fn from_iter<T: IntoIterator<Item = u8>>(iter: T) -> Self {
+		let mut report = Self::new();
+		for usage in iter {
+			report.press(usage);
+		}
+		report
+	}
+}
+
+/// Consumer usages a [`ConsumerReport`] holds at once.
+pub const CONSUMER_SLOTS: usize = 4;
+
+/// Consumer control report: the pressed usages, 16 bits each and little endian.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct ConsumerReport([u8; CONSUMER_SLOTS * 2]);
+
+impl ConsumerReport {
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.0
+	}
+
+	/// Usages past [`CONSUMER_SLOTS`] are left out.
+	pub fn press(&mut self, usage: u16) {
+		let mut slots = self.0.chunks_exact_mut(2);
+		if let Some(slot) = slots.find(|s| *s == [0, 0] || *s == usage.to_le_bytes()) {
+			slot.copy_from_slice(&usage.to_le_bytes());
+		}
+	}
+}
+
+impl FromIterator<u16> for ConsumerReport {
+	fn from_iter<T: IntoIterator<Item = u16>>(iter: T) -> Self {
+		let mut report = Self::default();
+		for usage in iter {
+			report.press(usage);
+		}
+		report
+	}
+}
+
+/// Highest consumer page usage [`CONSUMER_DESCRIPTOR`] declares.
+pub const CONSUMER_USAGE_MAX: u16 = 0x3FF;
+
+/// Report descriptor matching [`ConsumerReport`].
+pub const CONSUMER_DESCRIPTOR: &[u8] = &[
+	0x05, 0x0C, // Usage Page (Consumer)
+	0x09, 0x01, // Usage (Consumer Control)
+	0xA1, 0x01, // Collection (Application)
+	0x85, CONSUMER_REPORT_ID, //   Report ID
+	0x15, 0x00, //   Logical Minimum (0)
+	0x26, 0xFF, 0x03, //   Logical Maximum (0x3FF)
+	0x19, 0x00, //   Usage Minimum (0)
+	0x2A, 0xFF, 0x03, //   Usage Maximum (0x3FF)
+	0x75, 0x10, //   Report Size (16)
+	0x95, CONSUMER_SLOTS as u8, //   Report Count
+	0x81, 0x00, //   Input (Data, Array, Absolute)
+	0xC0, // End Collection
+];
+
+/// Relative mouse report, the wheel and pan in detents.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct MouseReport {
+	pub buttons: u8,
+	pub x: i8,
+	pub y: i8,
+	pub wheel: i8,
+	pub pan: i8,
+}
+
+impl MouseReport {
+	pub fn to_bytes(&self) -> [u8; 5] {
+		[self.buttons, self.x as u8, self.y as u8, self.wheel as u8, self.pan as u8]
+	}
+}
+
+/// Report descriptor matching [`MouseReport`].
+pub const MOUSE_DESCRIPTOR: &[u8] = &[
+	0x05, 0x01, // Usage Page (Generic Desktop)
+	0x09, 0x02, // Usage (Mouse)
+	0xA1, 0x01, // Collection (Application)
+	0x85, MOUSE_REPORT_ID, //   Report ID
+	0x09, 0x01, //   Usage (Pointer)
+	0xA1, 0x00, //   Collection (Physical)
+	0x05, 0x09, //     Usage Page (Buttons)
+	0x19, 0x01, //     Usage Minimum (1)
+	0x29, 0x05, //     Usage Maximum (5)
+	0x15, 0x00, //     Logical Minimum (0)
+	0x25, 0x01, //     Logical Maximum (1)
+	0x75, 0x01, //     Report Size (1)
+	0x95, 0x05, //     Report Count (5)
+	0x81, 0x02, //     Input (Data, Variable, Absolute)
+	0x95, 0x03, //     Report Count (3)
+	0x81, 0x01, //     Input (Constant)
+	0x05, 0x01, //     Usage Page (Generic Desktop)
+	0x09, 0x30, //     Usage (X)
+	0x09, 0x31, //     Usage (Y)
+	0x09, 0x38, //     Usage (Wheel)
+	0x15, 0x81, //     Logical Minimum (-127)
+	0x25, 0x7F, //     Logical Maximum (127)
+	0x75, 0x08, //     Report Size (8)
+	0x95, 0x03, //     Report Count (3)
+	0x81, 0x06, //     Input (Data, Variable, Relative)
+	0x05, 0x0C, //     Usage Page (Consumer)
+	0x0A, 0x38, 0x02, //     Usage (AC Pan)
+	0x95, 0x01, //     Report Count (1)
+	0x81, 0x06, //     Input (Data, Variable, Relative)
+	0xC0, //   End Collection
+	0xC0, // End Collection
+];
+
+/// Gamepad report: buttons, then the sticks and triggers as 16 bit little endian values.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct GamepadReport {
+	pub buttons: u16,
+	/// Left X and Y, right X and Y, from `-i16::MAX` to `i16::MAX`.
+	pub sticks: [i16; 4],
+	/// Left and right, from 0 to `i16::MAX`.
+	pub triggers: [i16; 2],
+}
+
+impl GamepadReport {
+	pub fn to_bytes(&self) -> [u8; 14] {
+		let mut bytes = [0; 14];
+		bytes[..2].copy_from_slice(&self.buttons.to_le_bytes());
+		for (i, value) in self.sticks.iter().chain(&self.triggers).enumerate() {
+			bytes[2 + 2 * i..4 + 2 * i].copy_from_slice(&value.to_le_bytes());
+		}
+		bytes
+	}
+}
+
+/// Report descriptor matching [`GamepadReport`], triggers on Z and Rz.
+pub const GAMEPAD_DESCRIPTOR: &[u8] = &[
+	0x05, 0x01, // Usage Page (Generic Desktop)
+	0x09, 0x05, // Usage (Game Pad)
+	0xA1, 0x01, // Collection (Application)
+	0x85, GAMEPAD_REPORT_ID, //   Report ID
+	0x05, 0x09, //   Usage Page (Buttons)
+	0x19, 0x01, //   Usage Minimum (1)
+	0x29, 0x10, //   Usage Maximum (16)
+	0x15, 0x00, //   Logical Minimum (0)
+	0x25, 0x01, //   Logical Maximum (1)
+	0x75, 0x01, //   Report Size (1)
+	0x95, 0x10, //   Report Count (16)
+	0x81, 0x02, //   Input (Data, Variable, Absolute)
+	0x05, 0x01, //   Usage Page (Generic Desktop)
+	0x09, 0x30, //   Usage (X)
+	0x09, 0x31, //   Usage (Y)
+	0x09, 0x33, //   Usage (Rx)
+	0x09, 0x34, //   Usage (Ry)
+	0x16, 0x01, 0x80, //   Logical Minimum (-32767)
+	0x26, 0xFF, 0x7F, //   Logical Maximum (32767)
+	0x75, 0x10, //   Report Size (16)
+	0x95, 0x04, //   Report Count (4)
+	0x81, 0x02, //   Input (Data, Variable, Absolute)
+	0x09, 0x32, //   Usage (Z)
+	0x09, 0x35, //   Usage (Rz)
+	0x15, 0x00, //   Logical Minimum (0)
+	0x95, 0x02, //   Report Count (2)
+	0x81, 0x02, //   Input (Data, Variable, Absolute)
+	0xC0, // End Collection
+];
+
+/// Vendor defined usage page of the analog collection, for finding it among the pad's HID collections.
+pub const ANALOG_USAGE_PAGE: u16 = 0xFF00;
+/// Keys in each analog report, boards with more keys send them in several pages.
+pub const ANALOG_PAGE_KEYS: usize = 16;
+pub const ANALOG_PAGES: usize = KEY_COUNT.div_ceil(ANALOG_PAGE_KEYS);
+/// Length of a page, its index and then two bytes per key.
+pub const ANALOG_PAGE_LEN: usize = 1 + 2 * ANALOG_PAGE_KEYS;
+/// Travel a key has to move before a new report is worth the endpoint it shares with the keys.
+pub const ANALOG_DEADBAND: u16 = 5;
+
+const _: () = assert!(ANALOG_PAGES <= u8::MAX as usize + 1, "page indices are a byte");
+
+/// How far every key is pressed, for applications reading the pad through hidraw.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AnalogReport {
+	/// From 0 at rest to [`FULL_TRAVEL`] bottomed out, in key order.
+	pub travel: [u16; KEY_COUNT],
+}
+
+impl AnalogReport {
+	pub const fn new() -> Self {
+		Self { travel: [0; KEY_COUNT] }
+	}
+
+	/// Keys of `page`, little endian, zero past the last key.
+	pub fn to_page(&self, page: usize) -> [u8; ANALOG_PAGE_LEN] {
+		let mut bytes = [0; ANALOG_PAGE_LEN];
+		bytes[0] = page as u8;
+		let keys = self.travel.iter().skip(page * ANALOG_PAGE_KEYS);
+		for (chunk, travel) in bytes[1..].chunks_exact_mut(2).zip(keys) {
+			chunk.copy_from_slice(&travel.to_le_bytes());
+		}
+		bytes
+	}
+
+	/// Whether a key moved more than [`ANALOG_DEADBAND`] since `previous`, or came to rest or bottomed out.
+	pub fn moved(&self, previous: &Self) -> bool {
+		self.travel.iter().zip(&previous.travel).any(|(&now, &then)| {
+			now.abs_diff(then) > ANALOG_DEADBAND || (now != then && (now == 0 || now >= FULL_TRAVEL))
+		})
+	}
+
+	/// Pages where `self` differs from `previous`.
+	pub fn changed_pages<'a>(&'a self, previous: &'a Self) -> impl Iterator<Item = usize> + 'a {
+		(0..ANALOG_PAGES).filter(move |page| {
+			let keys = page * ANALOG_PAGE_KEYS..KEY_COUNT.min((page + 1) * ANALOG_PAGE_KEYS);
+			self.travel[keys.clone()] != previous.travel[keys]
+		})
+	}
+
+	/// Takes in a report led by its ID, as hidraw reads them, returns false if it is not an analog page.
+	pub fn apply_report(&mut self, report: &[u8]) -> bool {
+		let Some((&ANALOG_REPORT_ID, page)) = report.split_first() else {
+			return false;
+		};
+		let Some((&index, keys)) = page.get(..ANALOG_PAGE_LEN).and_then(|page| page.split_first()) else {
+			return false;
+		};
+		let travel = self.travel.iter_mut().skip(index as usize * ANALOG_PAGE_KEYS);
+		for (travel, chunk) in travel.zip(keys.chunks_exact(2)) {
+			*travel = u16::from_le_bytes([chunk[0], chunk[1]]);
+		}
+		true
+	}
+
+	/// From 0.0 at rest to 1.0 bottomed out, `None` past the last key.
+	pub fn depth(&self, key: usize) -> Option<f32> {
+		let travel = self.travel.get(key)?;
+		Some(*travel.min(&FULL_TRAVEL) as f32 / FULL_TRAVEL as f32)
+	}
+}
+
+impl Default for AnalogReport {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Report descriptor matching [`AnalogReport::to_page`].
+pub const ANALOG_DESCRIPTOR: &[u8] = &[
+	0x06, ANALOG_USAGE_PAGE as u8, (ANALOG_USAGE_PAGE >> 8) as u8, // Usage Page (Vendor Defined)
+	0x09, 0x01, // Usage (1)
+	0xA1, 0x01, // Collection (Application)
+	0x85, ANALOG_REPORT_ID, //   Report ID
+	0x09, 0x03, //   Usage (3)
+	0x15, 0x00, //   Logical Minimum (0)
+	0x26, 0xFF, 0x00, //   Logical Maximum (255)
+	0x75, 0x08, //   Report Size (8)
+	0x95, 0x01, //   Report Count (1)
+	0x81, 0x02, //   Input (Data, Variable, Absolute)
+	0x09, 0x02, //   Usage (2)
+	0x26, FULL_TRAVEL as u8, (FULL_TRAVEL >> 8) as u8, //   Logical Maximum (FULL_TRAVEL)
+	0x75, 0x10, //   Report Size (16)
+	0x95, ANALOG_PAGE_KEYS as u8, //   Report Count (ANALOG_PAGE_KEYS)
+	0x81, 0x02, //   Input (Data, Variable, Absolute)
+	0xC0, // End Collection
+];
+
+/// Lock states from the host's LED output report, the same in both report formats.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Leds(pub u8);
+
+impl Leds {
+	pub fn num_lock(self) -> bool {
+		self.0 & 0x01 != 0
+	}
+
+	pub fn caps_lock(self) -> bool {
+		self.0 & 0x02 != 0
+	}
+
+	pub fn scroll_lock(self) -> bool {
+		self.0 & 0x04 != 0
+	}
+
+	pub fn compose(self) -> bool {
+		self.0 & 0x08 != 0
+	}
+
+	pub fn kana(self) -> bool {
+		self.0 & 0x10 != 0
+	}
+}
+
+/// Report descriptor matching [`BootReport`] and the LED output report, for hosts limited to six keys.
+pub const BOOT_DESCRIPTOR: &[u8] = &[
+	0x05, 0x01, // Usage Page (Generic Desktop)
+	0x09, 0x06, // Usage (Keyboard)
+	0xA1, 0x01, // Collection (Application)
+	0x85, KEYBOARD_REPORT_ID, //   Report ID
+	0x05, 0x07, //   Usage Page (Keyboard/Keypad)
+	0x19, 0xE0, //   Usage Minimum (Left Control)
+	0x29, 0xE7, //   Usage Maximum (Right GUI)
+	0x15, 0x00, //   Logical Minimum (0)
+	0x25, 0x01, //   Logical Maximum (1)
+	0x75, 0x01, //   Report Size (1)
+	0x95, 0x08, //   Report Count (8)
+	0x81, 0x02, //   Input (Data, Variable, Absolute)
+	0x75, 0x08, //   Report Size (8)
+	0x95, 0x01, //   Report Count (1)
+	0x81, 0x01, //   Input (Constant)
+	0x19, 0x00, //   Usage Minimum (0)
+	0x29, 0xFF, //   Usage Maximum (255)
+	0x26, 0xFF, 0x00, //   Logical Maximum (255)
+	0x95, 0x06, //   Report Count (6)
+	0x81, 0x00, //   Input (Data, Array, Absolute)
+	0x05, 0x08, //   Usage Page (LEDs)
+	0x19, 0x01, //   Usage Minimum (Num Lock)
+	0x29, 0x05, //   Usage Maximum (Kana)
+	0x25, 0x01, //   Logical Maximum (1)
+	0x75, 0x01, //   Report Size (1)
+	0x95, 0x05, //   Report Count (5)
+	0x91, 0x02, //   Output (Data, Variable, Absolute)
+	0x95, 0x03, //   Report Count (3)
+	0x91, 0x01, //   Output (Constant)
+	0xC0, // End Collection
+];
+
+/// Report descriptor matching [`NkroReport`], with the LED output report of a boot keyboard.
+pub const NKRO_DESCRIPTOR: &[u8] = &[
+	0x05, 0x01, // Usage Page (Generic Desktop)
+	0x09, 0x06, // Usage (Keyboard)
+	0xA1, 0x01, // Collection (Application)
+	0x85, KEYBOARD_REPORT_ID, //   Report ID
+	0x05, 0x07, //   Usage Page (Keyboard/Keypad)
+	0x19, 0xE0, //   Usage Minimum (Left Control)
+	0x29, 0xE7, //   Usage Maximum (Right GUI)
+	0x15, 0x00, //   Logical Minimum (0)
+	0x25, 0x01, //   Logical Maximum (1)
+	0x75, 0x01, //   Report Size (1)
+	0x95, 0x08, //   Report Count (8)
+	0x81, 0x02, //   Input (Data, Variable, Absolute)
+	0x19, 0x00, //   Usage Minimum (0)
+	0x29, MODIFIER_START - 1, //   Usage Maximum
+	0x95, MODIFIER_START, //   Report Count
+	0x81, 0x02, //   Input (Data, Variable, Absolute)
+	0x05, 0x08, //   Usage Page (LEDs)
+	0x19, 0x01, //   Usage Minimum (Num Lock)
+	0x29, 0x05, //   Usage Maximum (Kana)
+	0x95, 0x05, //   Report Count (5)
+	0x91, 0x02, //   Output (Data, Variable, Absolute)
+	0x95, 0x03, //   Report Count (3)
+	0x91, 0x01, //   Output (Constant)
+	0xC0, // End Collection
+];
+
+#[cfg(test)]
+mod test {
+	use std::vec::Vec;
+	use crate::config::Rollover;
+	use crate::key::FULL_TRAVEL;
+	use crate::keycode::usage;
+	use crate::keymap::KEY_COUNT;
+	use crate::report::{
+		report_descriptor, AnalogReport, ConsumerReport, NkroReport, ReportFormat, ANALOG_DEADBAND, ANALOG_PAGE_KEYS,
+		ANALOG_REPORT_ID, CONSUMER_REPORT_ID, GAMEPAD_REPORT_ID, KEYBOARD_REPORT_ID, MOUSE_REPORT_ID, NKRO_REPORT_LEN,
+		REPORT_DESCRIPTOR_MAX_LEN,
+	};
+
+	/// IDs in the order their Report ID items appear.
+	fn report_ids(descriptor: &[u8]) -> Vec<u8> {
+		let mut ids = Vec::new();
+		let mut i = 0;
+		while i < descriptor.len() {
+			let prefix = descriptor[i];
+			// Short items only, the size is in the low bits with 3 meaning 4 bytes
+			let size = [0, 1, 2, 4][(prefix & 0x03) as usize];
+			if prefix == 0x85 {
+				ids.push(descriptor[i + 1]);
+			}
+			i += 1 + size;
+		}
+		ids
+	}
+
+	#[test]
+	fn test_report_descriptor() {
+		for rollover in [Rollover::SixKey, Rollover::NKey] {
+			let mut buf = [0; REPORT_DESCRIPTOR_MAX_LEN];
+			let descriptor = report_descriptor(rollover, &mut buf);
+			let ids = [KEYBOARD_REPORT_ID, CONSUMER_REPORT_ID, MOUSE_REPORT_ID, GAMEPAD_REPORT_ID, ANALOG_REPORT_ID];
+			assert_eq!(report_ids(descriptor), ids);
+		}
+	}
+
+	#[test]
+	fn test_nkro() {
+		let usages = [0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0xE1];
+		let report: NkroReport = usages.into_iter().collect();
+
+		assert_eq!(report.as_bytes()[0], 0b10);
+		assert_eq!(report.as_bytes()[1], 0b1111_0000);
+		assert_eq!(report.usages().collect::<Vec<_>>(), usages);
+	}
+
+	#[test]
+	fn test_to_boot() {
+		let report: NkroReport = [0x04, 0x05, 0xE0].into_iter().collect();
+		assert_eq!(report.to_boot().as_bytes(), [0x01, 0, 0x04, 0x05, 0, 0, 0, 0]);
+
+		// Past six keys the boot report can only tell that there were too many
+		let report: NkroReport = (0x04..0x0B).collect();
+		assert_eq!(report.to_boot().as_bytes()[2..], [0x01; 6]);
+	}
+
+	#[test]
+	fn test_negotiated() {
+		assert_eq!(ReportFormat::negotiated(Rollover::NKey, false), ReportFormat::Nkro);
+		assert_eq!(ReportFormat::negotiated(Rollover::NKey, true), ReportFormat::Boot);
+		assert_eq!(ReportFormat::negotiated(Rollover::SixKey, false), ReportFormat::Boot);
+		assert_eq!(ReportFormat::negotiated(Rollover::SixKey, true), ReportFormat::Boot);
+	}
+
+	#[test]
+	fn test_encode() {
+		let a = usage("A").unwrap();
+		let escape = usage("Escape").unwrap();
+		let report: NkroReport = [a, escape, 0xE0, 0xE5].into_iter().collect();
+		let mut buf = [0; NKRO_REPORT_LEN];
+
+		// Modifiers, the reserved byte and the keys
+		assert_eq!(report.encode(ReportFormat::Boot, &mut buf), [0b0010_0001, 0, a, escape, 0, 0, 0, 0]);
+
+		let nkro = report.encode(ReportFormat::Nkro, &mut buf);
+		assert_eq!(nkro.len(), NKRO_REPORT_LEN);
+		assert_eq!(nkro[0], 0b0010_0001);
+		for key in [a, escape] {
+			assert_eq!(nkro[1 + key as usize / 8], 1 << (key % 8));
+		}
+		assert_eq!(nkro.iter().map(|b| b.count_ones()).sum::<u32>(), 4);
+	}
+
+	#[test]
+	fn test_empty() {
+		let mut buf = [0xFF; NKRO_REPORT_LEN];
+		assert_eq!(NkroReport::new().encode(ReportFormat::Boot, &mut buf), [0; 8]);
+		assert!(NkroReport::new().encode(ReportFormat::Nkro, &mut buf).iter().all(|b| *b == 0));
+	}
+
+	#[test]
+	fn test_consumer() {
+		let report: ConsumerReport = [0xE9, 0x0224, 0xE9].into_iter().collect();
+		assert_eq!(report.as_bytes(), [0xE9, 0x00, 0x24, 0x02, 0, 0, 0, 0]);
+
+		let report: ConsumerReport = (1..10).collect();
+		assert_eq!(report.as_bytes(), [1, 0, 2, 0, 3, 0, 4, 0]);
+	}
+
+	#[test]
+	fn test_analog() {
+		let mut report = AnalogReport::new();
+		report.travel[0] = FULL_TRAVEL / 4;
+		report.travel[KEY_COUNT - 1] = FULL_TRAVEL;
+
+		// What the host reads, each page led by the report ID
+		let mut parsed = AnalogReport::new();
+		for page in report.changed_pages(&AnalogReport::new()) {
+			let mut bytes = Vec::from([ANALOG_REPORT_ID]);
+			bytes.extend_from_slice(&report.to_page(page));
+			assert!(parsed.apply_report(&bytes));
+			assert!(!parsed.apply_report(&bytes[..bytes.len() - 1]));
+		}
+		assert_eq!(parsed, report);
+		assert!(!parsed.apply_report(&[KEYBOARD_REPORT_ID; 40]));
+
+		assert_eq!(report.depth(0), Some(0.25));
+		assert_eq!(report.depth(1), Some(0.0));
+		assert_eq!(report.depth(KEY_COUNT - 1), Some(1.0));
+		assert_eq!(report.depth(KEY_COUNT), None);
+	}
+
+	#[test]
+	fn test_analog_deadband() {
+		let mut previous = AnalogReport::new();
+		previous.travel[0] = 500;
+		let mut report = previous.clone();
+
+		report.travel[0] = 500 + ANALOG_DEADBAND;
+		assert!(!report.moved(&previous));
+		report.travel[0] = 500 + ANALOG_DEADBAND + 1;
+		assert!(report.moved(&previous));
+
+		// The ends always go out, so a released key reads 0
+		previous.travel[0] = ANALOG_DEADBAND;
+		report.travel[0] = 0;
+		assert!(report.moved(&previous));
+		report.travel[0] = ANALOG_DEADBAND;
+		assert!(!report.moved(&previous));
+	}
+
+	#[test]
+	fn test_analog_pages() {
+		let mut report = AnalogReport::new();
+		assert_eq!(report.changed_pages(&AnalogReport::new()).count(), 0);
+
+		report.travel[KEY_COUNT - 1] = 1;
+		let page = (KEY_COUNT - 1) / ANALOG_PAGE_KEYS;
+		assert_eq!(report.changed_pages(&AnalogReport::new()).collect::<Vec<_>>(), [page]);
+		assert_eq!(report.to_page(page)[0], page as u8);
+	}
+}
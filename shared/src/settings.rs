@@ -0,0 +1,84 @@
+use musli::{Decode, Encode};
+use crate::config::{Calibration, KeyConfig, ScanConfig, UsbConfig};
+use crate::gamepad::GamepadBinding;
+use crate::midi::MidiConfig;
+use crate::keymap::{Keymap, EMPTY_KEYMAP, KEY_COUNT, PROFILE_COUNT, PROFILE_NAME_LEN};
+use crate::mouse::MouseConfig;
+
+/// Everything the device persists, see `settings.rs` in the firmware.
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct Settings {
+	pub profiles: [Profile; PROFILE_COUNT],
+	pub keys: [KeyConfig; KEY_COUNT],
+	pub calibration: [Calibration; KEY_COUNT],
+	/// Lifetime presses per key.
+	pub counters: [u32; KEY_COUNT],
+	pub scan: ScanConfig,
+	pub usb: UsbConfig,
+	pub mouse: MouseConfig,
+}
+
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct Profile {
+	/// UTF-8, padded with zeroes.
+	#[musli(bytes)]
+	pub name: [u8; PROFILE_NAME_LEN],
+	pub keymap: Keymap,
+	/// Keys that also move the gamepad while this profile is active.
+	pub gamepad: [Option<GamepadBinding>; KEY_COUNT],
+	pub midi: MidiConfig,
+	/// MIDI note of each key, played next to whatever the key does in the keymap.
+	pub notes: [Option<u8>; KEY_COUNT],
+}
+
+impl Settings {
+	pub const fn empty() -> Self {
+		const PROFILE: Profile = Profile::empty();
+		Self {
+			profiles: [PROFILE; PROFILE_COUNT],
+			keys: [KeyConfig::DEFAULT; KEY_COUNT],
+			calibration: [Calibration { rest: None, bottom: None }; KEY_COUNT],
+			counters: [0; KEY_COUNT],
+			scan: ScanConfig::DEFAULT,
+			usb: UsbConfig::DEFAULT,
+			mouse: MouseConfig::DEFAULT,
+		}
+	}
+}
+
+impl Profile {
+	pub const fn empty() -> Self {
+		Self {
+			name: [0; PROFILE_NAME_LEN],
+			keymap: EMPTY_KEYMAP,
+			gamepad: [None; KEY_COUNT],
+			midi: MidiConfig::DEFAULT,
+			notes: [None; KEY_COUNT],
+		}
+	}
+
+	pub fn with_name(mut self, name: &str) -> Self {
+		self.name = encode_name(name);
+		self
+	}
+
+	pub fn name(&self) -> &str {
+		decode_name(&self.name)
+	}
+}
+
+/// Truncates names that are too long, on a char boundary.
+pub fn encode_name(name: &str) -> [u8; PROFILE_NAME_LEN] {
+	let mut len = name.len().min(PROFILE_NAME_LEN);
+	while !name.is_char_boundary(len) {
+		len -= 1;
+	}
+	let mut buf = [0; PROFILE_NAME_LEN];
+	buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+	buf
+}
+
+pub fn decode_name(name: &[u8; PROFILE_NAME_LEN]) -> &str {
+	let len = name.iter().position(|b| *b == 0).unwrap_or(PROFILE_NAME_LEN);
+	core::str::from_utf8(&name[..len]).unwrap_or("")
+}
@@ -0,0 +1,101 @@
+//! Timing of the fixed rate scan loop, to see whether the configured rate is actually kept.
+
+/// Times are in microseconds.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ScanStats {
+	/// As wide as the totals, so the averages stay right on a pad that runs for months.
+	pub scans: u64,
+	/// Ticks skipped because a scan ran into the next one.
+	pub missed: u32,
+	min: u32,
+	max: u32,
+	total: u64,
+	/// How late the worst scan started after its tick.
+	max_jitter: u32,
+	total_jitter: u64,
+	/// Longest time from a tick being due until the scan's reports were queued for USB.
+	max_delay: u32,
+}
+
+impl ScanStats {
+	pub const fn new() -> Self {
+		Self {
+			scans: 0,
+			missed: 0,
+			min: u32::MAX,
+			max: 0,
+			total: 0,
+			max_jitter: 0,
+			total_jitter: 0,
+			max_delay: 0,
+		}
+	}
+
+	/// A scan that started `jitter` after its tick and took `duration`,
+	/// with its reports queued `delay` after the tick.
+	pub fn record(&mut self, jitter: u32, duration: u32, delay: u32) {
+		self.scans += 1;
+		self.min = self.min.min(duration);
+		self.max = self.max.max(duration);
+		self.total += duration as u64;
+		self.max_jitter = self.max_jitter.max(jitter);
+		self.total_jitter += jitter as u64;
+		self.max_delay = self.max_delay.max(delay);
+	}
+
+	pub fn miss(&mut self, ticks: u32) {
+		self.missed = self.missed.saturating_add(ticks);
+	}
+
+	/// 0 before the first scan, like the rest.
+	pub fn min(&self) -> u32 {
+		if self.scans == 0 { 0 } else { self.min }
+	}
+
+	pub fn max(&self) -> u32 {
+		self.max
+	}
+
+	pub fn avg(&self) -> u32 {
+		self.total.checked_div(self.scans).unwrap_or(0) as u32
+	}
+
+	pub fn max_jitter(&self) -> u32 {
+		self.max_jitter
+	}
+
+	pub fn avg_jitter(&self) -> u32 {
+		self.total_jitter.checked_div(self.scans).unwrap_or(0) as u32
+	}
+
+	pub fn max_delay(&self) -> u32 {
+		self.max_delay
+	}
+}
+
+impl Default for ScanStats {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use crate::stats::ScanStats;
+
+	#[test]
+	fn test_stats() {
+		let mut stats = ScanStats::new();
+		assert_eq!((stats.min(), stats.avg(), stats.avg_jitter()), (0, 0, 0));
+
+		stats.record(2, 60, 40);
+		stats.record(0, 80, 75);
+		stats.record(10, 70, 60);
+		stats.miss(3);
+
+		assert_eq!((stats.min(), stats.avg(), stats.max()), (60, 70, 80));
+		assert_eq!((stats.avg_jitter(), stats.max_jitter()), (4, 10));
+		assert_eq!(stats.max_delay(), 75);
+		assert_eq!((stats.scans, stats.missed), (3, 3));
+	}
+}
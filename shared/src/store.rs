@@ -0,0 +1,150 @@
+//! Layout of persisted settings: a header followed by the encoded [`Settings`].
+//!
+//! The same image is written to flash by the firmware and to backup files by the CLI.
+
+use musli::FixedBytes;
+use crate::settings::Settings;
+use crate::ENCODING;
+
+pub const MAGIC: [u8; 4] = *b"MPAD";
+/// Bump whenever [`Settings`] changes shape, images of other versions are rejected.
+pub const VERSION: u16 = 15;
+pub const HEADER_LEN: usize = 16;
+/// Room for the settings of a full size keyboard.
+pub const PAYLOAD_MAX_LEN: usize = 16384;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StoreError {
+	/// No image was ever written, erased flash reads as all ones.
+	Empty,
+	Version(u16),
+	Length,
+	Checksum,
+	Encoding,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Header {
+	pub version: u16,
+	pub len: u16,
+	pub crc: u32,
+	/// Tells which of several stored images is the newest, meaningless in backup files.
+	pub sequence: u32,
+}
+
+impl Header {
+	pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+		let mut bytes = [0; HEADER_LEN];
+		bytes[0..4].copy_from_slice(&MAGIC);
+		bytes[4..6].copy_from_slice(&self.version.to_be_bytes());
+		bytes[6..8].copy_from_slice(&self.len.to_be_bytes());
+		bytes[8..12].copy_from_slice(&self.crc.to_be_bytes());
+		bytes[12..16].copy_from_slice(&self.sequence.to_be_bytes());
+		bytes
+	}
+
+	pub fn parse(bytes: &[u8]) -> Result<Self, StoreError> {
+		if bytes.len() < HEADER_LEN {
+			return Err(StoreError::Length);
+		}
+		if bytes[0..4] != MAGIC {
+			return Err(StoreError::Empty);
+		}
+		let header = Self {
+			version: u16::from_be_bytes([bytes[4], bytes[5]]),
+			len: u16::from_be_bytes([bytes[6], bytes[7]]),
+			crc: u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+			sequence: u32::from_be_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+		};
+		if header.version != VERSION {
+			return Err(StoreError::Version(header.version));
+		}
+		if header.len as usize > PAYLOAD_MAX_LEN {
+			return Err(StoreError::Length);
+		}
+		Ok(header)
+	}
+
+	/// Length of header and payload together.
+	pub fn image_len(&self) -> usize {
+		HEADER_LEN + self.len as usize
+	}
+}
+
+/// Encodes the payload into `buf` and returns the header describing it.
+pub fn encode(settings: &Settings, buf: &mut FixedBytes<PAYLOAD_MAX_LEN>) -> Result<Header, StoreError> {
+	buf.clear();
+	ENCODING.encode(&mut *buf, settings).map_err(|_| StoreError::Encoding)?;
+	Ok(Header {
+		version: VERSION,
+		len: buf.len() as u16,
+		crc: crc32(buf.as_slice()),
+		sequence: 0,
+	})
+}
+
+/// Decodes a complete image, header included.
+pub fn decode(image: &[u8]) -> Result<(Header, Settings), StoreError> {
+	let header = Header::parse(image)?;
+	let payload = image.get(HEADER_LEN..header.image_len()).ok_or(StoreError::Length)?;
+	Ok((header, decode_payload(&header, payload)?))
+}
+
+/// Decodes a payload that was transferred without its header.
+pub fn decode_payload(header: &Header, payload: &[u8]) -> Result<Settings, StoreError> {
+	if header.version != VERSION {
+		return Err(StoreError::Version(header.version));
+	}
+	if payload.len() != header.len as usize {
+		return Err(StoreError::Length);
+	}
+	if crc32(payload) != header.crc {
+		return Err(StoreError::Checksum);
+	}
+	ENCODING.decode(payload).map_err(|_| StoreError::Encoding)
+}
+
+/// CRC-32/ISO-HDLC, as used by zlib.
+pub fn crc32(data: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for byte in data {
+		crc ^= *byte as u32;
+		for _ in 0..8 {
+			let mask = (crc & 1).wrapping_neg();
+			crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+		}
+	}
+	!crc
+}
+
+#[cfg(test)]
+mod test {
+	use std::vec::Vec;
+	use musli::FixedBytes;
+	use crate::keymap::Action;
+	use crate::settings::{Profile, Settings};
+	use crate::store::{crc32, decode, encode, StoreError, HEADER_LEN};
+
+	#[test]
+	fn test_crc32() {
+		assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+	}
+
+	#[test]
+	fn test_roundtrip() {
+		let mut settings = Settings::empty();
+		settings.profiles[1] = Profile::empty().with_name("game");
+		settings.profiles[1].keymap[0][2] = Action::Key(0x04);
+
+		let mut buf = FixedBytes::new();
+		let header = encode(&settings, &mut buf).unwrap();
+		let mut image = Vec::from(header.to_bytes());
+		image.extend_from_slice(buf.as_slice());
+
+		assert_eq!(decode(&image), Ok((header, settings)));
+
+		image[HEADER_LEN] ^= 1;
+		assert_eq!(decode(&image), Err(StoreError::Checksum));
+		assert_eq!(decode(&[0xFF; 64]), Err(StoreError::Empty));
+	}
+}
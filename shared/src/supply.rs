@@ -0,0 +1,116 @@
+//! Compensation for the analog supply, using the internal VREFINT and temperature channels.
+//!
+//! ADC readings are relative to VDDA, so ripple on the USB supply moves every key at once.
+//! VREFINT is a fixed voltage, its reading tells how far VDDA is from where the factory measured it.
+
+/// VDDA the factory calibration values were read at.
+pub const CALIBRATION_MV: u32 = 3300;
+
+/// How quickly [`Supply`] follows VREFINT, each reading is weighted with `1 / 2^SMOOTHING`.
+const SMOOTHING: u32 = 3;
+
+/// Per chip readings written to system memory during production, all taken at [`CALIBRATION_MV`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FactoryCalibration {
+	pub vrefint: u16,
+	/// Temperature sensor at 30 °C.
+	pub ts_30: u16,
+	/// Temperature sensor at 110 °C.
+	pub ts_110: u16,
+}
+
+/// Averaged VREFINT, a single reading is too noisy to scale keys with.
+#[derive(Debug, Clone, Copy)]
+pub struct Supply {
+	calibration: FactoryCalibration,
+	/// In 1/256ths.
+	vrefint: u32,
+}
+
+impl Supply {
+	/// Assumes VDDA is where it was during calibration until the first [`Supply::measure`].
+	pub const fn new(calibration: FactoryCalibration) -> Self {
+		Self {
+			calibration,
+			vrefint: (calibration.vrefint as u32) << 8,
+		}
+	}
+
+	pub fn measure(&mut self, vrefint: u16) {
+		let reading = (vrefint as u32) << 8;
+		self.vrefint = self.vrefint - (self.vrefint >> SMOOTHING) + (reading >> SMOOTHING);
+	}
+
+	pub fn vdda_mv(&self) -> u16 {
+		let mv = CALIBRATION_MV as u64 * ((self.calibration.vrefint as u64) << 8) / self.vrefint.max(1) as u64;
+		mv.min(u16::MAX as u64) as u16
+	}
+
+	/// Scales a reading to what it would have been at [`CALIBRATION_MV`].
+	pub fn normalise(&self, value: u16) -> u16 {
+		let scaled = value as u64 * ((self.calibration.vrefint as u64) << 8) / self.vrefint.max(1) as u64;
+		scaled.min(u16::MAX as u64) as u16
+	}
+
+	/// Die temperature in hundredths of a degree, from a temperature sensor reading.
+	pub fn temperature(&self, sample: u16) -> i16 {
+		let FactoryCalibration { ts_30, ts_110, .. } = self.calibration;
+		let span = (ts_110 as i32 - ts_30 as i32).max(1);
+		let centi = (self.normalise(sample) as i32 - ts_30 as i32) * 8000 / span + 3000;
+		centi.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	const CALIBRATION: FactoryCalibration = FactoryCalibration { vrefint: 1500, ts_30: 950, ts_110: 1180 };
+
+	/// VREFINT as read with VDDA at `mv`.
+	fn vrefint_at(mv: u32) -> u16 {
+		(CALIBRATION.vrefint as u32 * CALIBRATION_MV / mv) as u16
+	}
+
+	fn settled(mv: u32) -> Supply {
+		let mut supply = Supply::new(CALIBRATION);
+		for _ in 0..200 {
+			supply.measure(vrefint_at(mv));
+		}
+		supply
+	}
+
+	#[test]
+	fn test_calibrated_supply_is_untouched() {
+		let supply = Supply::new(CALIBRATION);
+		assert_eq!(supply.vdda_mv(), 3300);
+		assert_eq!(supply.normalise(2000), 2000);
+	}
+
+	#[test]
+	fn test_normalises_sagging_supply() {
+		let supply = settled(3000);
+		assert!(supply.vdda_mv().abs_diff(3000) <= 5, "{}", supply.vdda_mv());
+		// A fixed 1.5V input reads higher once VDDA sags
+		let reading = (1500 * 4096 / 3000) as u16;
+		assert!(supply.normalise(reading).abs_diff((1500 * 4096 / 3300) as u16) <= 3);
+	}
+
+	#[test]
+	fn test_averages_ripple() {
+		let mut supply = Supply::new(CALIBRATION);
+		for i in 0..200 {
+			let mv = if i % 2 == 0 { 3200 } else { 3400 };
+			supply.measure(vrefint_at(mv));
+		}
+		assert!(supply.vdda_mv().abs_diff(3300) <= 20, "{}", supply.vdda_mv());
+	}
+
+	#[test]
+	fn test_temperature() {
+		let supply = Supply::new(CALIBRATION);
+		assert_eq!(supply.temperature(CALIBRATION.ts_30), 3000);
+		assert_eq!(supply.temperature(CALIBRATION.ts_110), 11000);
+		assert_eq!(supply.temperature(1065), 7000);
+	}
+}